@@ -0,0 +1,51 @@
+//! per call latency of `HttpTransport::post` against a local mock node,
+//! compared to opening a fresh connection for every request
+use criterion::{criterion_group, criterion_main, Criterion};
+use eth_rpc::HttpTransport;
+use oxhttp::{
+    model::{Method, Request},
+    Client,
+};
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+
+const CALL: &[u8] = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#;
+
+/// keep-alive capable mock answering every request with a block number
+fn mock_node() -> String {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", server.server_addr());
+    std::thread::spawn(move || {
+        let header = Header::from_bytes("content-type", "application/json").unwrap();
+        for mut req in server.incoming_requests() {
+            let mut body = Vec::new();
+            req.as_reader().read_to_end(&mut body).unwrap();
+            let res = Response::from_string(r#"{"jsonrpc":"2.0","id":1,"result":"0x10d4f"}"#)
+                .with_header(header.clone());
+            let _ = req.respond(res);
+        }
+    });
+    url
+}
+
+fn bench_http(c: &mut Criterion) {
+    let url = mock_node();
+    let transport = HttpTransport::try_from(url.clone()).unwrap();
+    let mut group = c.benchmark_group("http_post");
+    group.bench_function("pooled", |b| b.iter(|| transport.post(CALL).unwrap()));
+    group.bench_function("connection_per_call", |b| {
+        b.iter(|| {
+            let mut client = Client::new();
+            client.set_global_timeout(Duration::from_secs(10));
+            let req = Request::builder(Method::POST, url.parse().unwrap())
+                .with_header("content-type", "application/json")
+                .unwrap()
+                .with_body(CALL.to_vec());
+            client.request(req).unwrap().into_body().to_vec().unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_http);
+criterion_main!(benches);
@@ -1,11 +1,11 @@
 use super::{
     jr_call::JRCall,
+    layer::RpcRequest,
     rpc::{EthRpc, JRError, SafeJRResult},
 };
 use itertools::Itertools;
 use serde_json::Value;
-use std::time::Duration;
-use tracing::{debug, error, warn};
+use tracing::debug;
 
 impl EthRpc {
     /// returns all results that did not throw err on rpc level
@@ -19,12 +19,8 @@ impl EthRpc {
             .into_iter()
             .map(|chunk| {
                 let chunk: Vec<_> = chunk.into_iter().collect();
-                if self.disable_ratelimit_protection {
-                    debug!("DR: Getting batch chunk collect err: {}.", chunk.len());
-                    return self.dr_batch_chunk(chunk);
-                }
                 debug!("Getting batch chunk collect err: {}.", chunk.len());
-                self.no_ratelimit_batch_chunk(chunk)
+                self.layered_batch_chunk(chunk)
             })
             .collect();
         let mut result = Vec::new();
@@ -52,12 +48,8 @@ impl EthRpc {
             .into_iter()
             .map(|chunk| {
                 let chunk: Vec<_> = chunk.into_iter().collect();
-                if self.disable_ratelimit_protection {
-                    debug!("DR: Getting batch chunk: {}.", chunk.len());
-                    return self.dr_batch_chunk(chunk);
-                }
                 debug!("Getting batch chunk: {}.", chunk.len());
-                self.no_ratelimit_batch_chunk(chunk)
+                self.layered_batch_chunk(chunk)
             })
             .collect();
         let mut result = Vec::new();
@@ -72,52 +64,27 @@ impl EthRpc {
         if req_len != result.len() {
             return Err(JRError::BatchMissingResponses);
         }
-        // Note: responses length is checked in #no_ratelimit_batch_chunk
         Ok(result
             .into_iter()
             .sorted_by(|a, b| a.id.cmp(&b.id))
             .collect())
     }
-    /// no ratelimit and no network errors
-    fn no_ratelimit_batch_chunk(
+    /// through the layers, retried per `retry_policy` unless disabled
+    fn layered_batch_chunk(
         &self,
         requests: Vec<JRCall>,
     ) -> Result<(Vec<SafeJRResult>, Vec<JRError>), JRError> {
-        let request = serde_json::to_vec(&requests).map_err(|e| JRError::JRCallSerialize(e))?;
-        let mut backoff_sec = 1;
-        let mut count = 0;
-        loop {
-            count += 1;
-            let res = self.batch_chunk(request.as_slice());
-            if let Err(e) = res {
-                if count > 4 {
-                    error!("Retries fail: {:?}", e);
-                    return Err(e);
-                }
-                if e.is_network_or_ratelimit() {
-                    warn!("Batch rpc: network err or rate limited!");
-                    std::thread::sleep(Duration::from_secs(backoff_sec));
-                    backoff_sec += 1;
-                    continue;
-                }
-                // error!("{:?}", e);
-                return Err(e);
-            }
-
-            return res;
-        }
+        let res = self.call_layers(&RpcRequest::Batch(requests))?;
+        Ok((res.results, res.errs))
     }
-    fn dr_batch_chunk(
+    /// chunk is subset of batch
+    pub(crate) fn batch_chunk(
         &self,
-        requests: Vec<JRCall>,
+        requests: &[u8],
+        methods: &[&str],
     ) -> Result<(Vec<SafeJRResult>, Vec<JRError>), JRError> {
-        let request = serde_json::to_vec(&requests).map_err(|e| JRError::JRCallSerialize(e))?;
-        self.batch_chunk(request.as_slice())
-    }
-    /// chunk is subset of batch
-    fn batch_chunk(&self, requests: &[u8]) -> Result<(Vec<SafeJRResult>, Vec<JRError>), JRError> {
         // we call the underlying because it is rpc batch so it returns vec instead of value
-        let response: Vec<Value> = self.__call_transport(requests)?;
+        let response: Vec<Value> = self.__call_transport(requests, methods)?;
         let mut res: Vec<SafeJRResult> = Vec::new();
         let mut errs = Vec::new();
         for r in response {
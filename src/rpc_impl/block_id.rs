@@ -0,0 +1,161 @@
+use ethers::types::{H256, U256, U64};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+/// block to read the state at, `latest` by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlockId {
+    Number(u64),
+    /// [EIP-1898](https://eips.ethereum.org/EIPS/eip-1898), with `require_canonical`
+    /// the node errors instead of answering from an uncled block
+    Hash {
+        hash: H256,
+        require_canonical: bool,
+    },
+    #[default]
+    Latest,
+    Pending,
+    Safe,
+    Finalized,
+    Earliest,
+}
+
+impl BlockId {
+    /// must still be in the canonical chain
+    pub fn canonical(hash: H256) -> Self {
+        Self::Hash {
+            hash,
+            require_canonical: true,
+        }
+    }
+
+    pub fn is_hash(&self) -> bool {
+        matches!(self, Self::Hash { .. })
+    }
+}
+
+impl From<u64> for BlockId {
+    fn from(number: u64) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl From<U64> for BlockId {
+    fn from(number: U64) -> Self {
+        Self::Number(number.as_u64())
+    }
+}
+
+/// fails above `u64::MAX`
+impl TryFrom<U256> for BlockId {
+    type Error = String;
+    fn try_from(number: U256) -> Result<Self, Self::Error> {
+        if number > U256::from(u64::MAX) {
+            return Err(format!("block number {} is above u64", number));
+        }
+        Ok(Self::Number(number.as_u64()))
+    }
+}
+
+/// not required to be canonical, see `BlockId::canonical`
+impl From<H256> for BlockId {
+    fn from(hash: H256) -> Self {
+        Self::Hash {
+            hash,
+            require_canonical: false,
+        }
+    }
+}
+
+/// a quantity or tag, hashes as `{"blockHash": ..}` objects that
+/// pre EIP-1898 nodes and `eth_getBlockByNumber` don't accept
+impl Serialize for BlockId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Number(number) => serializer.serialize_str(&format!("{:#x}", number)),
+            Self::Hash {
+                hash,
+                require_canonical,
+            } => {
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("blockHash", hash)?;
+                // left out when false, some nodes reject the unknown field
+                if *require_canonical {
+                    map.serialize_entry("requireCanonical", &true)?;
+                }
+                map.end()
+            }
+            Self::Latest => serializer.serialize_str("latest"),
+            Self::Pending => serializer.serialize_str("pending"),
+            Self::Safe => serializer.serialize_str("safe"),
+            Self::Finalized => serializer.serialize_str("finalized"),
+            Self::Earliest => serializer.serialize_str("earliest"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_mock::{recorded_params, recording};
+    use ethers::types::H160;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize() {
+        let hash = H256::repeat_byte(0xab);
+        let cases = [
+            (BlockId::from(17633288u64), json!("0x10d1008")),
+            (BlockId::Number(0), json!("0x0")),
+            (BlockId::default(), json!("latest")),
+            (BlockId::Pending, json!("pending")),
+            (BlockId::Safe, json!("safe")),
+            (BlockId::Finalized, json!("finalized")),
+            (BlockId::Earliest, json!("earliest")),
+            (BlockId::from(hash), json!({ "blockHash": hash })),
+            (
+                BlockId::canonical(hash),
+                json!({"blockHash": hash, "requireCanonical": true}),
+            ),
+        ];
+        for (block, expected) in cases {
+            assert_eq!(serde_json::to_value(block).unwrap(), expected);
+        }
+        assert_eq!(BlockId::try_from(U256::from(16)), Ok(BlockId::Number(16)));
+        assert!(BlockId::try_from(U256::MAX).is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        let (client, sent) = recording(|_| Ok(json!("0x1")));
+        let address = H160::zero();
+        let hash = H256::repeat_byte(1);
+
+        client.get_balance_at(address, BlockId::Safe).unwrap();
+        client
+            .get_transaction_count_at(address, BlockId::canonical(hash))
+            .unwrap();
+        client
+            .get_storage_slot_at(address, U256::one(), BlockId::Number(16))
+            .unwrap();
+        // old signatures
+        client.get_latest_balance(address).unwrap();
+        client.get_transaction_count_at_block(address, 16).unwrap();
+        client.get_storage_at(address, U256::one(), 16).unwrap();
+
+        let address = json!(address);
+        assert_eq!(
+            recorded_params(&sent),
+            vec![
+                json!([address, "safe"]),
+                json!([address, {"blockHash": hash, "requireCanonical": true}]),
+                json!([address, "0x1", "0x10"]),
+                json!([address, "latest"]),
+                json!([address, "0x10"]),
+                json!([address, "0x1", "0x10"]),
+            ]
+        );
+    }
+}
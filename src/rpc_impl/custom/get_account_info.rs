@@ -1,4 +1,4 @@
-use crate::{JRCall, EthRpc, JRError};
+use crate::{BlockId, JRCall, EthRpc, JRError};
 use ethers::types::{H160, U256};
 use revm::{
     interpreter::analysis::to_analysed,
@@ -29,12 +29,8 @@ pub struct AccountPartial {
     pub nonce: u64,
 }
 
-fn account_params(address: H160, block: u64) -> Result<Vec<Value>, JRError> {
-    // U256 is crucial on block value as it serializes as hex
-    Ok(vec![
-        JRCall::to_value(address)?,
-        JRCall::to_value(U256::from(block))?,
-    ])
+fn account_params(address: H160, block: BlockId) -> Result<Vec<Value>, JRError> {
+    Ok(vec![JRCall::to_value(address)?, JRCall::to_value(block)?])
 }
 
 pub fn get_code_hash_and_code(code: String) -> Result<(B256, Option<Bytecode>), JRError> {
@@ -52,6 +48,14 @@ pub fn get_code_hash_and_code(code: String) -> Result<(B256, Option<Bytecode>),
 
 impl EthRpc {
     pub fn get_account_info(&self, address: H160, block: u64) -> Result<AccountInfo, JRError> {
+        self.get_account_info_at(address, block.into())
+    }
+
+    pub fn get_account_info_at(
+        &self,
+        address: H160,
+        block: BlockId,
+    ) -> Result<AccountInfo, JRError> {
         let params = account_params(address, block)?;
         // the length is already checked
         let mut result = self.batch(vec![
@@ -76,6 +80,14 @@ impl EthRpc {
         &self,
         address: H160,
         block: u64,
+    ) -> Result<AccountPartial, JRError> {
+        self.get_account_partial_at(address, block.into())
+    }
+
+    pub fn get_account_partial_at(
+        &self,
+        address: H160,
+        block: BlockId,
     ) -> Result<AccountPartial, JRError> {
         let params = account_params(address, block)?;
         // the length is already checked
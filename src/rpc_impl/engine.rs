@@ -0,0 +1,106 @@
+use super::{EthRpc, JRCall, JRError};
+use crate::{HttpAuth, HttpTransport, JwtSecret, RpcTransport};
+use ethers::types::{Bytes, H160, U64};
+use serde::{Deserialize, Serialize};
+
+/// `engine_getPayloadBodiesByRangeV1` item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayloadBodyV1 {
+    pub transactions: Vec<Bytes>,
+    /// `None` before shanghai
+    pub withdrawals: Option<Vec<Withdrawal>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Withdrawal {
+    pub index: U64,
+    pub validator_index: U64,
+    pub address: H160,
+    /// in gwei
+    pub amount: U64,
+}
+
+impl EthRpc {
+    /// authenticated engine port (8551) of your own node, `jwt_secret_path` is the hex secret file
+    ///
+    /// over ipc `engine_*` needs no auth, use `RpcTransport::Ipc`
+    pub fn with_engine(
+        http: impl ToString,
+        jwt_secret_path: impl ToString,
+    ) -> Result<Self, JRError> {
+        let http = HttpTransport::try_from(http.to_string()).map_err(JRError::FailInitialize)?;
+        let secret = JwtSecret::from_file(jwt_secret_path).map_err(JRError::FailInitialize)?;
+        Ok(Self::with_transport(RpcTransport::Http(
+            http.with_auth(HttpAuth::Jwt(secret)),
+        )))
+    }
+
+    /// methods supported by both sides
+    pub fn engine_exchange_capabilities(
+        &self,
+        capabilities: Vec<String>,
+    ) -> Result<Vec<String>, JRError> {
+        let payload = JRCall::new("engine_exchangeCapabilities", vec![capabilities])?;
+        self.no_ratelimit_rpc(payload)
+    }
+
+    /// `None` for blocks the node does not have
+    pub fn engine_get_payload_bodies_by_range_v1(
+        &self,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<Option<PayloadBodyV1>>, JRError> {
+        let payload = JRCall::new(
+            "engine_getPayloadBodiesByRangeV1",
+            vec![U64::from(start), U64::from(count)],
+        )?;
+        self.no_ratelimit_rpc(payload)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_mock::{serve, MockResponse};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_engine_over_jwt() {
+        let url = serve(|req| {
+            let auth = req
+                .headers
+                .get("authorization")
+                .cloned()
+                .unwrap_or_default();
+            if !auth.starts_with("Bearer ") || auth.split('.').count() != 3 {
+                return MockResponse::status(401, "");
+            }
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            let result = match call["method"].as_str().unwrap() {
+                "engine_exchangeCapabilities" => call["params"][0].clone(),
+                _ => {
+                    assert_eq!(call["params"], json!(["0x10", "0x2"]));
+                    json!([{
+                        "transactions": ["0x02f8"],
+                        "withdrawals": [{"index": "0x1", "validatorIndex": "0x2", "address": H160::zero(), "amount": "0x3"}]
+                    }, null])
+                }
+            };
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+        });
+        let path = std::env::temp_dir().join(format!("eth_rpc_engine_{}.hex", std::process::id()));
+        std::fs::write(&path, "11".repeat(32)).unwrap();
+        let client = EthRpc::with_engine(url, path.to_str().unwrap()).unwrap();
+        let caps = vec!["engine_getPayloadBodiesByRangeV1".to_string()];
+        assert_eq!(
+            client.engine_exchange_capabilities(caps.clone()).unwrap(),
+            caps
+        );
+        let bodies = client.engine_get_payload_bodies_by_range_v1(16, 2).unwrap();
+        assert_eq!(bodies.len(), 2);
+        let withdrawals = bodies[0].as_ref().unwrap().withdrawals.as_ref().unwrap();
+        assert_eq!(withdrawals[0].validator_index, U64::from(2));
+        assert!(bodies[1].is_none());
+    }
+}
@@ -1,14 +1,20 @@
-use crate::{EthRpc, JRError};
+use super::{multicall::Multicall, MulticallError};
+use crate::{BlockId, EthRpc, JRError};
 use ethers::{
+    abi::AbiDecode,
     prelude::abigen,
-    types::{H160, U256},
+    types::{Bytes, H160, U256},
 };
+use serde::Serialize;
 
+// also used by the tests of the other calls
 abigen!(
     _Erc20,
     r#"[
+    function name() external view returns (string)
     function decimals() external view returns (uint8)
     function symbol() external view returns (string)
+    function totalSupply() external view returns (uint256)
     function balanceOf(address account) external view returns (uint256)
 ]"#
 );
@@ -20,9 +26,21 @@ abigen!(
 ]"#
 );
 
+/// plain storage reads, far below the default of a multicall call
+const METADATA_CALL_GAS: u64 = 30_000;
+
 impl EthRpc {
     pub fn get_balance(&self, token: H160, account: H160) -> Result<U256, JRError> {
-        let b: BalanceOfReturn = self.eth_call_typed(token, BalanceOfCall { account })?;
+        self.get_token_balance_at(token, account, BlockId::Latest)
+    }
+
+    pub fn get_token_balance_at(
+        &self,
+        token: H160,
+        account: H160,
+        block: BlockId,
+    ) -> Result<U256, JRError> {
+        let b: BalanceOfReturn = self.eth_call_typed_at(token, BalanceOfCall { account }, block)?;
         Ok(b.0)
     }
 
@@ -35,19 +53,225 @@ impl EthRpc {
     pub fn get_bytes32_symbol(&self, token: H160) -> Result<String, JRError> {
         use erc_21::{SymbolCall, SymbolReturn};
         let s: SymbolReturn = self.eth_call_typed(token, SymbolCall {})?;
-        let result = String::from_utf8_lossy(s.0.as_slice()).to_string();
-        Ok(result.trim().trim_end_matches(char::from(0)).to_string())
+        Ok(bytes32_to_string(s.0))
     }
 
     pub fn get_decimals(&self, token: H160) -> Result<u8, JRError> {
         let d: DecimalsReturn = self.eth_call_typed(token, DecimalsCall {})?;
         Ok(d.0)
     }
+
+    pub fn get_token_metadata(&self, tokens: &[H160]) -> Result<Vec<TokenMetadata>, JRError> {
+        self.get_token_metadata_at(tokens, BlockId::Latest)
+    }
+
+    /// all tokens in one multicall, `string` and MKR like `bytes32` names and symbols alike.
+    /// a few hundred tokens fit in a single `eth_call`
+    pub fn get_token_metadata_at(
+        &self,
+        tokens: &[H160],
+        block: BlockId,
+    ) -> Result<Vec<TokenMetadata>, JRError> {
+        let mut multicall = Multicall::new().with_block(block);
+        let handles: Vec<_> = tokens
+            .iter()
+            .map(|&token| {
+                (
+                    multicall.add_with_gas::<Bytes>(token, NameCall {}, true, METADATA_CALL_GAS),
+                    multicall.add_with_gas::<Bytes>(
+                        token,
+                        erc_20::SymbolCall {},
+                        true,
+                        METADATA_CALL_GAS,
+                    ),
+                    multicall.add_with_gas::<u8>(token, DecimalsCall {}, true, METADATA_CALL_GAS),
+                    multicall.add_with_gas::<U256>(
+                        token,
+                        TotalSupplyCall {},
+                        true,
+                        METADATA_CALL_GAS,
+                    ),
+                )
+            })
+            .collect();
+        let results = self.multicall(&multicall)?;
+        let string = |handle| results.raw(handle).ok().and_then(decode_string);
+        Ok(tokens
+            .iter()
+            .zip(handles)
+            .map(
+                |(&address, (name, symbol, decimals, total_supply))| TokenMetadata {
+                    address,
+                    name: string(name),
+                    symbol: string(symbol),
+                    decimals: results.get(decimals).ok(),
+                    total_supply: results.get(total_supply).ok(),
+                },
+            )
+            .collect())
+    }
+
+    /// `balanceOf` of many holders of one token, in the order of `holders`
+    pub fn get_balances(
+        &self,
+        token: H160,
+        holders: &[H160],
+    ) -> Result<Vec<Result<U256, MulticallError>>, JRError> {
+        self.get_balances_at(token, holders, BlockId::Latest)
+    }
+
+    pub fn get_balances_at(
+        &self,
+        token: H160,
+        holders: &[H160],
+        block: BlockId,
+    ) -> Result<Vec<Result<U256, MulticallError>>, JRError> {
+        let calls = holders
+            .iter()
+            .map(|&account| (token, BalanceOfCall { account }))
+            .collect();
+        self.multicall_typed(calls, block)
+    }
+
+    /// `balanceOf` of one holder in many tokens, in the order of `tokens`
+    pub fn get_balances_of(
+        &self,
+        holder: H160,
+        tokens: &[H160],
+    ) -> Result<Vec<Result<U256, MulticallError>>, JRError> {
+        self.get_balances_of_at(holder, tokens, BlockId::Latest)
+    }
+
+    pub fn get_balances_of_at(
+        &self,
+        holder: H160,
+        tokens: &[H160],
+        block: BlockId,
+    ) -> Result<Vec<Result<U256, MulticallError>>, JRError> {
+        let calls = tokens
+            .iter()
+            .map(|&token| (token, BalanceOfCall { account: holder }))
+            .collect();
+        self.multicall_typed(calls, block)
+    }
+}
+
+/// `None` where the token doesn't implement the call or returned garbage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TokenMetadata {
+    pub address: H160,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub total_supply: Option<U256>,
+}
+
+fn bytes32_to_string(bytes: [u8; 32]) -> String {
+    let result = String::from_utf8_lossy(bytes.as_slice()).to_string();
+    result.trim().trim_end_matches(char::from(0)).to_string()
+}
+
+/// `string` first, then `bytes32`
+fn decode_string(raw: &Bytes) -> Option<String> {
+    String::decode(raw)
+        .ok()
+        .or_else(|| <[u8; 32]>::decode(raw).ok().map(bytes32_to_string))
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::multicall::{Aggregate3Call, Aggregate3Return};
     use super::*;
+    use crate::http_mock::{recording, Recorded};
+    use ethers::abi::AbiEncode;
+    use serde_json::json;
+
+    /// token 1 like usdc, token 2 like mkr, token 3 only knows `balanceOf`,
+    /// balances are the last byte of the holder times the last byte of the token
+    fn client() -> (EthRpc, Recorded) {
+        recording(|call| {
+            let data: Bytes = serde_json::from_value(call["params"][0]["data"].clone()).unwrap();
+            let return_data = Aggregate3Call::decode(&data)
+                .unwrap()
+                .calls
+                .into_iter()
+                .map(|c| {
+                    let token = c.target.0[19];
+                    let mkr = |s: &str| {
+                        let mut b = [0u8; 32];
+                        b[..s.len()].copy_from_slice(s.as_bytes());
+                        b.encode()
+                    };
+                    let raw = match (token, _Erc20Calls::decode(&c.call_data).unwrap()) {
+                        (_, _Erc20Calls::BalanceOf(b)) => {
+                            U256::from(b.account.0[19] as u64 * token as u64).encode()
+                        }
+                        (1, _Erc20Calls::Name(_)) => "USD Coin".to_string().encode(),
+                        (1, _Erc20Calls::Symbol(_)) => "USDC".to_string().encode(),
+                        (1, _Erc20Calls::Decimals(_)) => 6u8.encode(),
+                        (1, _Erc20Calls::TotalSupply(_)) => U256::from(1000).encode(),
+                        (2, _Erc20Calls::Name(_)) => mkr("Maker"),
+                        (2, _Erc20Calls::Symbol(_)) => mkr("MKR"),
+                        (2, _Erc20Calls::Decimals(_)) => 18u8.encode(),
+                        (2, _Erc20Calls::TotalSupply(_)) => U256::from(2000).encode(),
+                        _ => return (false, Bytes::default()),
+                    };
+                    (true, Bytes::from(raw))
+                })
+                .collect();
+            Ok(json!(Bytes::from(
+                Aggregate3Return { return_data }.encode()
+            )))
+        })
+    }
+
+    #[test]
+    fn test_token_metadata() {
+        let (client, requests) = client();
+        let tokens: Vec<_> = (1..=3).map(H160::from_low_u64_be).collect();
+        let metadata = client.get_token_metadata(&tokens).unwrap();
+        assert_eq!(
+            metadata,
+            vec![
+                TokenMetadata {
+                    address: tokens[0],
+                    name: Some("USD Coin".into()),
+                    symbol: Some("USDC".into()),
+                    decimals: Some(6),
+                    total_supply: Some(1000.into()),
+                },
+                TokenMetadata {
+                    address: tokens[1],
+                    name: Some("Maker".into()),
+                    symbol: Some("MKR".into()),
+                    decimals: Some(18),
+                    total_supply: Some(2000.into()),
+                },
+                TokenMetadata {
+                    address: tokens[2],
+                    ..Default::default()
+                },
+            ]
+        );
+        // a typical token list is a single round trip
+        let tokens: Vec<_> = (1..=200).map(H160::from_low_u64_be).collect();
+        client.get_token_metadata(&tokens).unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_balances() {
+        let (client, _) = client();
+        let holders: Vec<_> = (1..=3).map(H160::from_low_u64_be).collect();
+        let balances = client
+            .get_balances(H160::from_low_u64_be(2), &holders)
+            .unwrap();
+        assert_eq!(balances, vec![Ok(2.into()), Ok(4.into()), Ok(6.into())]);
+        let balances = client
+            .get_balances_of(H160::from_low_u64_be(5), &holders)
+            .unwrap();
+        assert_eq!(balances, vec![Ok(5.into()), Ok(10.into()), Ok(15.into())]);
+    }
 
     #[test]
     fn test_erc20_functions() {
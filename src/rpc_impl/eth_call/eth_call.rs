@@ -1,4 +1,5 @@
-use crate::{JRCall, EthRpc, JRError};
+use super::CallOverrides;
+use crate::{BlockId, JRCall, EthRpc, JRError};
 use ethers::{
     abi::{AbiDecode, AbiEncode},
     types::{transaction::eip2718::TypedTransaction, Bytes, H160, U256},
@@ -6,18 +7,58 @@ use ethers::{
 
 impl EthRpc {
     pub fn eth_call(&self, tx: TypedTransaction) -> Result<Bytes, JRError> {
-        let payload = JRCall::new(
-            "eth_call",
-            vec![
-                serde_json::to_value(tx).map_err(|e| JRError::JRCallSerialize(e))?,
-                serde_json::to_value("latest").map_err(|e| JRError::JRCallSerialize(e))?,
-            ],
-        )?;
-        let v: Bytes = self.no_ratelimit_rpc(payload)?;
+        self.eth_call_at(tx, BlockId::Latest)
+    }
+    pub fn eth_call_at(&self, tx: TypedTransaction, block: BlockId) -> Result<Bytes, JRError> {
+        self.eth_call_with(tx, block, &CallOverrides::default())
+    }
+    /// against overridden state and block header, geth, erigon, reth and nethermind only.
+    /// without overrides only `[tx, block]` is sent
+    pub fn eth_call_with(
+        &self,
+        tx: TypedTransaction,
+        block: BlockId,
+        overrides: &CallOverrides,
+    ) -> Result<Bytes, JRError> {
+        let mut params = vec![JRCall::to_value(tx)?, JRCall::to_value(block)?];
+        if !overrides.state.is_empty() || overrides.block.is_some() {
+            params.push(JRCall::to_value(&overrides.state)?);
+        }
+        if let Some(block) = &overrides.block {
+            params.push(JRCall::to_value(block)?);
+        }
+        let payload = JRCall::new("eth_call", params)?;
+        let v: Bytes = self
+            .no_ratelimit_rpc(payload)
+            .map_err(JRError::decode_reverted)?;
         Ok(v)
     }
     /// simple wrapper on top of eth call to work with abigen! macro
     pub fn eth_call_typed<R>(&self, to: H160, calldata: impl AbiEncode) -> Result<R, JRError>
+    where
+        R: AbiDecode,
+    {
+        self.eth_call_typed_at(to, calldata, BlockId::Latest)
+    }
+    pub fn eth_call_typed_at<R>(
+        &self,
+        to: H160,
+        calldata: impl AbiEncode,
+        block: BlockId,
+    ) -> Result<R, JRError>
+    where
+        R: AbiDecode,
+    {
+        self.eth_call_typed_with(to, calldata, block, &CallOverrides::default())
+    }
+    /// abigen calls against overridden state, see `eth_call_with`
+    pub fn eth_call_typed_with<R>(
+        &self,
+        to: H160,
+        calldata: impl AbiEncode,
+        block: BlockId,
+        overrides: &CallOverrides,
+    ) -> Result<R, JRError>
     where
         R: AbiDecode,
     {
@@ -26,7 +67,7 @@ impl EthRpc {
         tx.set_to(to);
         tx.set_data(input);
         tx.set_value(U256::from(0));
-        let bytes = self.eth_call(tx)?;
+        let bytes = self.eth_call_with(tx, block, overrides)?;
         Ok(R::decode(&bytes).map_err(|e| JRError::Extension(format!("{:?}", e)))?)
     }
 }
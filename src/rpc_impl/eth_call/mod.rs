@@ -1,3 +1,10 @@
-mod erc20;
+pub(crate) mod erc20;
 mod eth_call;
+mod multicall;
+mod overrides;
+pub use erc20::TokenMetadata;
 pub use eth_call::*;
+pub use multicall::{
+    CallHandle, Multicall, MulticallError, MulticallResults, DEFAULT_CALL_GAS, MULTICALL3,
+};
+pub use overrides::{AccountOverride, BlockOverrides, CallOverrides};
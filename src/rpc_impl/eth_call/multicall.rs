@@ -0,0 +1,382 @@
+use crate::{BlockId, EthRpc, JRError, RevertReason};
+use ethers::{
+    abi::{AbiDecode, AbiEncode},
+    prelude::abigen,
+    types::{transaction::eip2718::TypedTransaction, Bytes, H160},
+};
+use std::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::debug;
+
+abigen!(
+    _Multicall3,
+    r#"[
+    struct Call3 { address target; bool allowFailure; bytes callData; }
+    struct Result3 { bool success; bytes returnData; }
+    function aggregate3(Call3[] calls) external payable returns (Result3[] returnData)
+]"#
+);
+
+/// same address on every chain it is deployed to, see <https://www.multicall3.com>
+pub const MULTICALL3: H160 = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+/// enough for balances, allowances and metadata reads
+pub const DEFAULT_CALL_GAS: u64 = 100_000;
+
+/// calls to run through Multicall3's `aggregate3`, see `EthRpc::multicall`
+#[derive(Debug, Clone)]
+pub struct Multicall {
+    pub address: H160,
+    pub block: BlockId,
+    /// chunks are cut so that the gas of their calls stays below,
+    /// also sent as the gas of each `eth_call`
+    pub max_gas_per_chunk: u64,
+    calls: Vec<Call>,
+    /// handed to its `CallHandle`s, clones share it
+    id: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Call {
+    target: H160,
+    allow_failure: bool,
+    data: Bytes,
+    gas: u64,
+}
+
+/// index of a call with the type its result decodes into
+#[derive(Debug)]
+pub struct CallHandle<R> {
+    pub index: usize,
+    multicall: u64,
+    _result: PhantomData<fn() -> R>,
+}
+
+impl<R> Clone for CallHandle<R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R> Copy for CallHandle<R> {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MulticallError {
+    /// only with `allow_failure`, otherwise the whole chunk reverts
+    Reverted { reason: RevertReason, raw: Bytes },
+    /// return data doesn't decode into the expected type
+    Decode(String),
+    /// the handle is of another multicall
+    UnknownCall(usize),
+}
+
+impl std::fmt::Display for MulticallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reverted {
+                reason: RevertReason::Custom,
+                raw,
+            } => write!(f, "call reverted with custom error {}", raw),
+            Self::Reverted { reason, .. } => write!(f, "call reverted: {}", reason),
+            Self::Decode(e) => write!(f, "failed to decode the call result: {}", e),
+            Self::UnknownCall(index) => write!(f, "no result for call {}", index),
+        }
+    }
+}
+
+impl std::error::Error for MulticallError {}
+
+/// the raw return data of every call, in the order they were added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticallResults {
+    pub results: Vec<(bool, Bytes)>,
+    multicall: u64,
+}
+
+impl Default for Multicall {
+    fn default() -> Self {
+        Self {
+            address: MULTICALL3,
+            block: BlockId::Latest,
+            max_gas_per_chunk: 25_000_000,
+            calls: Vec::new(),
+            id: {
+                static IDS: AtomicU64 = AtomicU64::new(0);
+                IDS.fetch_add(1, Ordering::Relaxed)
+            },
+        }
+    }
+}
+
+impl Multicall {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// for chains where it lives elsewhere
+    pub fn with_address(mut self, address: H160) -> Self {
+        self.address = address;
+        self
+    }
+    pub fn with_block(mut self, block: BlockId) -> Self {
+        self.block = block;
+        self
+    }
+    pub fn with_max_gas_per_chunk(mut self, gas: u64) -> Self {
+        self.max_gas_per_chunk = gas;
+        self
+    }
+    /// a failing call without `allow_failure` fails its whole chunk
+    pub fn add<R: AbiDecode>(
+        &mut self,
+        target: H160,
+        call: impl AbiEncode,
+        allow_failure: bool,
+    ) -> CallHandle<R> {
+        self.add_with_gas(target, call, allow_failure, DEFAULT_CALL_GAS)
+    }
+    /// `gas` is the share of the chunk's budget, not a limit of the call
+    pub fn add_with_gas<R: AbiDecode>(
+        &mut self,
+        target: H160,
+        call: impl AbiEncode,
+        allow_failure: bool,
+        gas: u64,
+    ) -> CallHandle<R> {
+        self.calls.push(Call {
+            target,
+            allow_failure,
+            data: call.encode().into(),
+            gas,
+        });
+        CallHandle {
+            index: self.calls.len() - 1,
+            multicall: self.id,
+            _result: PhantomData,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// consecutive calls within the gas budget, a single call over it gets its own chunk
+    fn chunks(&self) -> Vec<&[Call]> {
+        let mut chunks = Vec::new();
+        let (mut start, mut gas) = (0, 0);
+        for (i, call) in self.calls.iter().enumerate() {
+            if i > start && gas + call.gas > self.max_gas_per_chunk {
+                chunks.push(&self.calls[start..i]);
+                start = i;
+                gas = 0;
+            }
+            gas += call.gas;
+        }
+        if start < self.calls.len() {
+            chunks.push(&self.calls[start..]);
+        }
+        chunks
+    }
+}
+
+impl MulticallResults {
+    pub fn get<R: AbiDecode>(&self, handle: CallHandle<R>) -> Result<R, MulticallError> {
+        let raw = self.raw(handle)?;
+        R::decode(raw).map_err(|e| MulticallError::Decode(e.to_string()))
+    }
+    /// undecoded return data of a call that succeeded
+    pub fn raw<R>(&self, handle: CallHandle<R>) -> Result<&Bytes, MulticallError> {
+        if handle.multicall != self.multicall {
+            return Err(MulticallError::UnknownCall(handle.index));
+        }
+        match self.results.get(handle.index) {
+            Some((true, raw)) => Ok(raw),
+            Some((false, raw)) => Err(MulticallError::Reverted {
+                reason: RevertReason::decode(raw),
+                raw: raw.clone(),
+            }),
+            None => Err(MulticallError::UnknownCall(handle.index)),
+        }
+    }
+}
+
+impl EthRpc {
+    /// one `eth_call` per gas bounded chunk, fails on the first chunk that fails
+    pub fn multicall(&self, multicall: &Multicall) -> Result<MulticallResults, JRError> {
+        let mut results = Vec::with_capacity(multicall.len());
+        for chunk in multicall.chunks() {
+            debug!("Multicall chunk: {}.", chunk.len());
+            let calls = chunk
+                .iter()
+                .map(|c| Call3 {
+                    target: c.target,
+                    allow_failure: c.allow_failure,
+                    call_data: c.data.clone(),
+                })
+                .collect();
+            let mut tx = TypedTransaction::default();
+            tx.set_to(multicall.address);
+            tx.set_data(Aggregate3Call { calls }.encode().into());
+            tx.set_gas(multicall.max_gas_per_chunk);
+            let bytes = self.eth_call_at(tx, multicall.block)?;
+            let returned = Aggregate3Return::decode(&bytes)
+                .map_err(|e| JRError::Extension(format!("{:?}", e)))?
+                .return_data;
+            if returned.len() != chunk.len() {
+                return Err(JRError::Extension(format!(
+                    "multicall returned {} results for {} calls",
+                    returned.len(),
+                    chunk.len()
+                )));
+            }
+            results.extend(returned);
+        }
+        Ok(MulticallResults {
+            results,
+            multicall: multicall.id,
+        })
+    }
+
+    /// the same call shape on many targets, e.g. `balanceOf` of many tokens, failures allowed
+    pub fn multicall_typed<C, R>(
+        &self,
+        calls: Vec<(H160, C)>,
+        block: BlockId,
+    ) -> Result<Vec<Result<R, MulticallError>>, JRError>
+    where
+        C: AbiEncode,
+        R: AbiDecode,
+    {
+        let mut multicall = Multicall::new().with_block(block);
+        let handles: Vec<CallHandle<R>> = calls
+            .into_iter()
+            .map(|(target, call)| multicall.add(target, call, true))
+            .collect();
+        let results = self.multicall(&multicall)?;
+        Ok(handles.into_iter().map(|h| results.get(h)).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::erc20::{erc_20::SymbolCall, BalanceOfCall, BalanceOfReturn};
+    use super::*;
+    use crate::http_mock::{recording, Recorded};
+    use ethers::{abi::Token, types::U256};
+    use serde_json::json;
+
+    /// reverts on calls to `0x00..00`, answers with the last byte of the target otherwise
+    fn client() -> (EthRpc, Recorded) {
+        recording(|call| {
+            let data: Bytes = serde_json::from_value(call["params"][0]["data"].clone()).unwrap();
+            let calls = Aggregate3Call::decode(&data).unwrap().calls;
+            let mut returned = Vec::new();
+            for call3 in calls {
+                if !call3.target.is_zero() {
+                    let value = U256::from(call3.target.0[19]);
+                    returned.push((true, Bytes::from(value.encode())));
+                    continue;
+                }
+                if !call3.allow_failure {
+                    return Err(
+                        json!({"code": 3, "message": "execution reverted: Multicall3: call failed"}),
+                    );
+                }
+                let mut revert = vec![0x08, 0xc3, 0x79, 0xa0];
+                revert.extend(ethers::abi::encode(&[Token::String("nope".into())]));
+                returned.push((false, Bytes::from(revert)));
+            }
+            let result = Aggregate3Return {
+                return_data: returned,
+            };
+            Ok(json!(Bytes::from(result.encode())))
+        })
+    }
+
+    #[test]
+    fn test_multicall() {
+        let (client, requests) = client();
+        let mut multicall = Multicall::new()
+            .with_block(BlockId::Number(1))
+            .with_max_gas_per_chunk(250_000);
+        let balance = multicall.add::<U256>(
+            H160::from_low_u64_be(5),
+            BalanceOfCall {
+                account: H160::zero(),
+            },
+            false,
+        );
+        let reverted = multicall.add::<U256>(H160::zero(), SymbolCall {}, true);
+        // a uint doesn't decode as a string
+        let symbol = multicall.add::<String>(H160::from_low_u64_be(1), SymbolCall {}, true);
+        let big = multicall.add_with_gas::<U256>(
+            H160::from_low_u64_be(9),
+            SymbolCall {},
+            false,
+            1_000_000,
+        );
+        assert_eq!(
+            multicall
+                .chunks()
+                .iter()
+                .map(|c| c.len())
+                .collect::<Vec<_>>(),
+            vec![2, 1, 1]
+        );
+
+        let results = client.multicall(&multicall).unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(results.get(balance), Ok(5.into()));
+        assert_eq!(
+            results.get(reverted),
+            Err(MulticallError::Reverted {
+                reason: RevertReason::Error("nope".into()),
+                raw: results.results[1].1.clone(),
+            })
+        );
+        assert!(matches!(
+            results.get(symbol),
+            Err(MulticallError::Decode(_))
+        ));
+        assert_eq!(results.get(big), Ok(9.into()));
+        // a handle of another multicall, even one in range
+        let mut other = Multicall::new();
+        let unknown = other.add::<U256>(H160::zero(), SymbolCall {}, true);
+        assert_eq!(results.get(unknown), Err(MulticallError::UnknownCall(0)));
+
+        // required call failing fails the request
+        let mut multicall = Multicall::new();
+        multicall.add::<U256>(H160::zero(), SymbolCall {}, false);
+        assert!(matches!(
+            client.multicall(&multicall).unwrap_err(),
+            JRError::Reverted { .. }
+        ));
+    }
+
+    #[test]
+    fn test_multicall_typed() {
+        let (client, _) = client();
+        let calls = [3, 0, 7]
+            .into_iter()
+            .map(|t| {
+                (
+                    H160::from_low_u64_be(t),
+                    BalanceOfCall {
+                        account: H160::zero(),
+                    },
+                )
+            })
+            .collect();
+        let balances: Vec<Result<BalanceOfReturn, _>> =
+            client.multicall_typed(calls, BlockId::Latest).unwrap();
+        assert_eq!(balances[0], Ok(BalanceOfReturn(3.into())));
+        assert!(balances[1].is_err());
+        assert_eq!(balances[2], Ok(BalanceOfReturn(7.into())));
+    }
+}
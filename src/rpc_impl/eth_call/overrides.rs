@@ -0,0 +1,192 @@
+use ethers::types::{Bytes, H160, H256, U256, U64};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// geth's per account state override, unset fields keep the real state.
+/// `state` and `state_diff` exclude each other, nodes reject both at once
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    /// replaces the whole storage, unlisted slots read as zero
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<BTreeMap<H256, H256>>,
+    /// patches single slots on top of the real storage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<BTreeMap<H256, H256>>,
+}
+
+/// header fields of the simulated block, as named by geth 1.14
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_limit: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_recipient: Option<H160>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_randao: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_base_fee: Option<U256>,
+}
+
+/// third and fourth param of `eth_call`, see `EthRpc::eth_call_with`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOverrides {
+    pub state: BTreeMap<H160, AccountOverride>,
+    pub block: Option<BlockOverrides>,
+}
+
+impl CallOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_balance(mut self, address: H160, balance: U256) -> Self {
+        self.account(address).balance = Some(balance);
+        self
+    }
+    pub fn with_nonce(mut self, address: H160, nonce: u64) -> Self {
+        self.account(address).nonce = Some(nonce.into());
+        self
+    }
+    /// runtime bytecode, not the init code
+    pub fn with_code(mut self, address: H160, code: impl Into<Bytes>) -> Self {
+        self.account(address).code = Some(code.into());
+        self
+    }
+    /// replaces the slots set with `with_slot` so far, later ones are added to `state`
+    pub fn with_state(mut self, address: H160, state: BTreeMap<H256, H256>) -> Self {
+        let account = self.account(address);
+        account.state = Some(state);
+        account.state_diff = None;
+        self
+    }
+    /// a single storage slot, the others keep their real values, or read as zero after `with_state`
+    pub fn with_slot(mut self, address: H160, slot: H256, value: H256) -> Self {
+        let account = self.account(address);
+        match &mut account.state {
+            Some(state) => state,
+            None => account.state_diff.get_or_insert_with(Default::default),
+        }
+        .insert(slot, value);
+        self
+    }
+    pub fn with_block(mut self, block: BlockOverrides) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    fn account(&mut self, address: H160) -> &mut AccountOverride {
+        self.state.entry(address).or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::erc20::BalanceOfCall;
+    use super::*;
+    use crate::{
+        http_mock::{recorded_params, recording},
+        BlockId,
+    };
+    use ethers::{abi::AbiEncode, types::transaction::eip2718::TypedTransaction};
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize() {
+        let token = H160::repeat_byte(1);
+        let holder = H160::repeat_byte(2);
+        let overrides = CallOverrides::new()
+            .with_balance(holder, U256::exp10(18))
+            .with_nonce(holder, 5)
+            .with_code(token, vec![0x60, 0x00])
+            .with_slot(token, H256::zero(), H256::from_low_u64_be(1))
+            .with_slot(token, H256::from_low_u64_be(3), H256::from_low_u64_be(2));
+        assert_eq!(
+            serde_json::to_value(&overrides.state).unwrap(),
+            json!({
+                format!("{:?}", token): {
+                    "code": "0x6000",
+                    "stateDiff": {
+                        format!("{:?}", H256::zero()): H256::from_low_u64_be(1),
+                        format!("{:?}", H256::from_low_u64_be(3)): H256::from_low_u64_be(2),
+                    }
+                },
+                format!("{:?}", holder): {"balance": "0xde0b6b3a7640000", "nonce": "0x5"},
+            })
+        );
+        // never both, nodes reject that
+        let slot = |i| H256::from_low_u64_be(i);
+        let overrides = CallOverrides::new()
+            .with_slot(token, slot(1), slot(1))
+            .with_state(token, BTreeMap::from([(slot(2), slot(2))]))
+            .with_slot(token, slot(3), slot(3));
+        assert_eq!(
+            overrides.state[&token],
+            AccountOverride {
+                state: Some(BTreeMap::from([(slot(2), slot(2)), (slot(3), slot(3))])),
+                ..Default::default()
+            }
+        );
+        let block = BlockOverrides {
+            number: Some(20.into()),
+            base_fee_per_gas: Some(U256::zero()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(block).unwrap(),
+            json!({"number": "0x14", "baseFeePerGas": "0x0"})
+        );
+    }
+
+    #[test]
+    fn test_eth_call_with() {
+        let (client, sent) = recording(|_| Ok(json!(Bytes::from(U256::from(7).encode()))));
+        let token = H160::repeat_byte(1);
+        let account = H160::repeat_byte(2);
+
+        let overrides = CallOverrides::new().with_slot(token, H256::zero(), H256::zero());
+        let balance: U256 = client
+            .eth_call_typed_with(
+                token,
+                BalanceOfCall { account },
+                BlockId::Pending,
+                &overrides,
+            )
+            .unwrap();
+        assert_eq!(balance, 7.into());
+
+        let block = BlockOverrides {
+            time: Some(1.into()),
+            ..Default::default()
+        };
+        let overrides = CallOverrides::new().with_block(block);
+        client
+            .eth_call_with(TypedTransaction::default(), BlockId::Latest, &overrides)
+            .unwrap();
+        // nothing extra without overrides
+        client.eth_call(TypedTransaction::default()).unwrap();
+
+        let sent = recorded_params(&sent);
+        assert_eq!(sent[0][1], json!("pending"));
+        assert_eq!(
+            sent[0][2],
+            json!({format!("{:?}", token): {"stateDiff": {format!("{:?}", H256::zero()): H256::zero()}}})
+        );
+        assert_eq!(sent[0].as_array().unwrap().len(), 3);
+        assert_eq!(sent[1][2], json!({}));
+        assert_eq!(sent[1][3], json!({"time": "0x1"}));
+        assert_eq!(sent[2].as_array().unwrap().len(), 2);
+    }
+}
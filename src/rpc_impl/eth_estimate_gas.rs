@@ -1,10 +1,23 @@
-use super::{JRCall, EthRpc, JRError};
+use super::{BlockId, JRCall, EthRpc, JRError};
 use ethers::types::{transaction::eip2718::TypedTransaction, U256};
 
 impl EthRpc {
+    /// without a block param, for nodes that don't take one
     pub fn estimate_gas(&self, tx: TypedTransaction) -> Result<u64, JRError> {
         let payload = JRCall::new("eth_estimateGas", vec![tx])?;
-        let v: U256 = self.no_ratelimit_rpc(payload)?;
+        self.estimate(payload)
+    }
+    pub fn estimate_gas_at(&self, tx: TypedTransaction, block: BlockId) -> Result<u64, JRError> {
+        let payload = JRCall::new(
+            "eth_estimateGas",
+            vec![JRCall::to_value(tx)?, JRCall::to_value(block)?],
+        )?;
+        self.estimate(payload)
+    }
+    fn estimate(&self, payload: JRCall) -> Result<u64, JRError> {
+        let v: U256 = self
+            .no_ratelimit_rpc(payload)
+            .map_err(JRError::decode_reverted)?;
         Ok(v.as_u64())
     }
 }
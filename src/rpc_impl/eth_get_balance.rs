@@ -1,11 +1,15 @@
-use crate::{EthRpc, JRCall, JRError};
+use crate::{BlockId, EthRpc, JRCall, JRError};
 use ethers::types::{H160, U256};
 
 impl EthRpc {
     pub fn get_latest_balance(&self, target: H160) -> Result<U256, JRError> {
+        self.get_balance_at(target, BlockId::Latest)
+    }
+    /// native balance, `get_balance` and `get_token_balance_at` are the erc20 ones
+    pub fn get_balance_at(&self, target: H160, block: BlockId) -> Result<U256, JRError> {
         let payload = JRCall::new(
             "eth_getBalance",
-            vec![JRCall::to_value(target)?, JRCall::to_value("latest")?],
+            vec![JRCall::to_value(target)?, JRCall::to_value(block)?],
         )?;
         self.no_ratelimit_rpc(payload)
     }
@@ -1,7 +1,8 @@
-use super::{EthRpc, JRCall, JRError};
+use super::{BlockId, EthRpc, JRCall, JRError};
 use ethers::types::{Block, H256, U256};
 
 impl EthRpc {
+    /// sent as is, numbers above u64 are left for the node to reject
     pub fn get_block_by_number(&self, number: U256) -> Result<Block<H256>, JRError> {
         let payload = JRCall::new(
             "eth_getBlockByNumber",
@@ -10,23 +11,23 @@ impl EthRpc {
         self.no_ratelimit_rpc(payload)
     }
     pub fn get_latest_block(&self) -> Result<Block<H256>, JRError> {
-        let payload = JRCall::new(
-            "eth_getBlockByNumber",
-            vec![
-                JRCall::to_value(String::from("latest"))?,
-                JRCall::to_value(false)?,
-            ],
-        )?;
-        self.no_ratelimit_rpc(payload)
+        self.get_block(BlockId::Latest)
     }
     pub fn get_pending_block(&self) -> Result<Block<H256>, JRError> {
-        let payload = JRCall::new(
-            "eth_getBlockByNumber",
-            vec![
-                JRCall::to_value(String::from("pending"))?,
-                JRCall::to_value(false)?,
-            ],
-        )?;
+        self.get_block(BlockId::Pending)
+    }
+    /// `eth_getBlockByHash` for hashes, `requireCanonical` doesn't apply there
+    pub fn get_block(&self, block: BlockId) -> Result<Block<H256>, JRError> {
+        let payload = match block {
+            BlockId::Hash { hash, .. } => JRCall::new(
+                "eth_getBlockByHash",
+                vec![JRCall::to_value(hash)?, JRCall::to_value(false)?],
+            )?,
+            block => JRCall::new(
+                "eth_getBlockByNumber",
+                vec![JRCall::to_value(block)?, JRCall::to_value(false)?],
+            )?,
+        };
         self.no_ratelimit_rpc(payload)
     }
 }
@@ -1,14 +1,23 @@
-use super::{JRCall, EthRpc, JRError};
+use super::{BlockId, JRCall, EthRpc, JRError};
 use ethers::types::{H160, U256};
 
 impl EthRpc {
     pub fn get_storage_at(&self, address: H160, index: U256, block: u64) -> Result<U256, JRError> {
+        self.get_storage_slot_at(address, index, block.into())
+    }
+    /// `eth_getStorageAt` at any block
+    pub fn get_storage_slot_at(
+        &self,
+        address: H160,
+        index: U256,
+        block: BlockId,
+    ) -> Result<U256, JRError> {
         let payload = JRCall::new(
             "eth_getStorageAt",
             vec![
-                format!("{:?}", address),
-                format!("0x{:x}", index),
-                format!("0x{:x}", block),
+                JRCall::to_value(address)?,
+                JRCall::to_value(format!("0x{:x}", index))?,
+                JRCall::to_value(block)?,
             ],
         )?;
         self.no_ratelimit_rpc(payload)
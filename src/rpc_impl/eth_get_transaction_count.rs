@@ -1,21 +1,21 @@
-use super::{JRCall, EthRpc, JRError};
+use super::{BlockId, JRCall, EthRpc, JRError};
 use ethers::types::{H160, U256};
 
 impl EthRpc {
     pub fn get_transaction_count(&self, address: H160) -> Result<u64, JRError> {
-        self.get_transaction_count_at(address, "latest".into())
+        self.get_transaction_count_at(address, BlockId::Latest)
     }
     pub fn get_transaction_count_at_block(
         &self,
         address: H160,
         block: u64,
     ) -> Result<u64, JRError> {
-        self.get_transaction_count_at(address, format!("{:#x}", block))
+        self.get_transaction_count_at(address, block.into())
     }
-    fn get_transaction_count_at(&self, address: H160, block: String) -> Result<u64, JRError> {
+    pub fn get_transaction_count_at(&self, address: H160, block: BlockId) -> Result<u64, JRError> {
         let payload = JRCall::new(
             "eth_getTransactionCount",
-            vec![format!("{:?}", address), block],
+            vec![JRCall::to_value(address)?, JRCall::to_value(block)?],
         )?;
         let v: U256 = self.no_ratelimit_rpc(payload)?;
         Ok(v.as_u64())
@@ -1,47 +1,326 @@
-use super::{JRCall, EthRpc, JRError};
-use ethers::types::{Bytes, H256};
+use super::{JRCall, EthRpc, JRError, JsonRpcError};
+use ethers::types::{Bytes, H256, U256};
 
+/// rejections of geth, erigon, nethermind, besu, reth (and the hosted providers running them),
+/// values are parsed where the client sends them
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum SubmitTxError {
     JRErr(JRError),
     NonceTooLow,
     ReplacementUnderpriced,
+    /// max fee per gas below the base fee, with the node's message
     BaseGasPriceTooLow(String),
+    /// nonce gap, the tx would wait in the queue
+    NonceTooHigh {
+        nonce: Option<u64>,
+        expected: Option<u64>,
+    },
+    AlreadyKnown,
+    /// below the pool's minimum gas price or tip
+    Underpriced,
+    /// balance lower than gas * price + value
+    InsufficientFunds {
+        have: Option<U256>,
+        want: Option<U256>,
+    },
+    /// gas limit lower than the intrinsic gas
+    IntrinsicGasTooLow {
+        have: Option<u64>,
+        want: Option<u64>,
+    },
+    ExceedsBlockGasLimit,
+    /// fee above the node's `--rpc.txfeecap`
+    ExceedsFeeCap,
+    TxPoolFull,
+    /// max priority fee per gas higher than max fee per gas
+    FeeCapBelowTip,
+    ChainIdMismatch {
+        have: Option<u64>,
+        want: Option<u64>,
+    },
+    /// pre EIP-155 signature
+    NotReplayProtected,
+    TxTypeNotSupported,
+    OversizedData,
+    /// max fee per blob gas below the blob base fee
+    BlobFeeCapTooLow,
+    TooManyBlobs,
+    /// blob tx without blob hashes or sidecar
+    BlobTxMissingBlobs,
+    /// the sender has pending txs of the other kind (blob vs. non blob)
+    BlobPoolConflict,
 }
 
-impl EthRpc {
-    pub fn send_raw_tx(&self, bytes: Bytes) -> Result<H256, SubmitTxError> {
-        let payload = JRCall::new("eth_sendRawTransaction", vec![bytes])
-            .map_err(|e| SubmitTxError::JRErr(e))?;
-        match self.call_rpc_transport(
-            payload
-                .to_vec()
-                .map_err(|e| SubmitTxError::JRErr(e))?
-                .as_slice(),
-        ) {
-            Ok(v) => Ok(v),
-            Err(e) => {
-                if let JRError::JsonRpcResultError(v) = &e {
-                    let stringified = serde_json::to_string(v).unwrap();
-                    if stringified.contains("nonce too low") {
-                        return Err(SubmitTxError::NonceTooLow);
-                    }
-                    if stringified.contains("replacement transaction underpriced") {
-                        return Err(SubmitTxError::ReplacementUnderpriced);
-                    }
-                    if stringified.contains("max fee per gas less than block base fee") {
-                        return Err(SubmitTxError::BaseGasPriceTooLow(stringified));
-                    }
+type Classify = fn(&JsonRpcError) -> SubmitTxError;
+
+/// lowercase snippets of the error message (and data) in order of precedence
+const CLASSIFIERS: &[(&[&str], Classify)] = &[
+    (
+        &[
+            "replacement transaction underpriced",
+            "replacementnotallowed",
+            "there is another transaction with same nonce",
+        ],
+        |_| SubmitTxError::ReplacementUnderpriced,
+    ),
+    (
+        &[
+            "already known",
+            "alreadyknown",
+            "known transaction",
+            "already imported",
+        ],
+        |_| SubmitTxError::AlreadyKnown,
+    ),
+    (
+        &["nonce too low", "oldnonce", "nonce is too low"],
+        |_| SubmitTxError::NonceTooLow,
+    ),
+    (
+        &[
+            "nonce too high",
+            "noncegap",
+            "too distant from current sender nonce",
+        ],
+        |e| SubmitTxError::NonceTooHigh {
+            nonce: number_after(e, "tx:").map(|n| n.low_u64()),
+            expected: number_after(e, "state:").map(|n| n.low_u64()),
+        },
+    ),
+    (
+        &[
+            "insufficient funds",
+            "insufficientfunds",
+            "upfront cost exceeds account balance",
+        ],
+        |e| SubmitTxError::InsufficientFunds {
+            have: number_after(e, "have"),
+            want: number_after(e, "want"),
+        },
+    ),
+    (
+        &[
+            "intrinsic gas too low",
+            "intrinsic gas exceeds gas limit",
+            "intrinsicgastoolow",
+        ],
+        |e| SubmitTxError::IntrinsicGasTooLow {
+            have: number_after(e, "have").map(|n| n.low_u64()),
+            want: number_after(e, "want").map(|n| n.low_u64()),
+        },
+    ),
+    (
+        &["exceeds block gas limit", "gaslimitexceeded"],
+        |_| SubmitTxError::ExceedsBlockGasLimit,
+    ),
+    (
+        &["txpool is full", "transaction pool is full"],
+        |_| SubmitTxError::TxPoolFull,
+    ),
+    (
+        &[
+            "max priority fee per gas higher than max fee per gas",
+            "max priority fee per gas exceeds max fee per gas",
+        ],
+        |_| SubmitTxError::FeeCapBelowTip,
+    ),
+    (
+        &["max fee per blob gas less than", "blob fee cap too low"],
+        |_| SubmitTxError::BlobFeeCapTooLow,
+    ),
+    (
+        &[
+            "max fee per gas less than block base fee",
+            "fee cap less than block base fee",
+            "gas price below current base fee",
+        ],
+        |e| SubmitTxError::BaseGasPriceTooLow(e.message.clone()),
+    ),
+    (
+        &[
+            "transaction underpriced",
+            "feetoolow",
+            "fee too low",
+            "gas price below configured minimum gas price",
+        ],
+        |_| SubmitTxError::Underpriced,
+    ),
+    (
+        &[
+            "invalid chain id",
+            "chain id mismatch",
+            "wrong chainid",
+            "meant for chain id",
+        ],
+        |e| SubmitTxError::ChainIdMismatch {
+            have: number_after(e, "have").map(|n| n.low_u64()),
+            want: number_after(e, "want").map(|n| n.low_u64()),
+        },
+    ),
+    (
+        &["only replay-protected"],
+        |_| SubmitTxError::NotReplayProtected,
+    ),
+    (
+        &["exceeds the configured cap", "transaction fee cap exceeded"],
+        |_| SubmitTxError::ExceedsFeeCap,
+    ),
+    (
+        &["address already reserved", "pendingtxsofconflictingtype"],
+        |_| SubmitTxError::BlobPoolConflict,
+    ),
+    (
+        &["blobless blob transaction", "missing blob hashes"],
+        |_| SubmitTxError::BlobTxMissingBlobs,
+    ),
+    (&["too many blobs"], |_| SubmitTxError::TooManyBlobs),
+    (
+        &["transaction type not supported", "notsupportedtxtype"],
+        |_| SubmitTxError::TxTypeNotSupported,
+    ),
+    (
+        &["oversized data", "maxtxsizeexceeded"],
+        |_| SubmitTxError::OversizedData,
+    ),
+];
+
+/// decimal number following `key`, e.g. `have 1000` or `state: 5`
+fn number_after(e: &JsonRpcError, key: &str) -> Option<U256> {
+    let text = e.text();
+    let (_, rest) = text.split_once(key)?;
+    let digits: String = rest
+        .trim_start_matches([' ', ':'])
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    U256::from_dec_str(&digits).ok()
+}
+
+impl From<JRError> for SubmitTxError {
+    fn from(e: JRError) -> Self {
+        if let Some(err) = e.json_rpc_error() {
+            let text = err.text();
+            for (snippets, classify) in CLASSIFIERS {
+                if snippets.iter().any(|s| text.contains(s)) {
+                    return classify(&err);
                 }
-                return Err(SubmitTxError::JRErr(e));
             }
         }
+        Self::JRErr(e)
+    }
+}
+
+impl SubmitTxError {
+    /// stable name for grouping in logs and alerts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::JRErr(e) => e.kind(),
+            Self::NonceTooLow => "nonce_too_low",
+            Self::ReplacementUnderpriced => "replacement_underpriced",
+            Self::BaseGasPriceTooLow(_) => "base_gas_price_too_low",
+            Self::NonceTooHigh { .. } => "nonce_too_high",
+            Self::AlreadyKnown => "already_known",
+            Self::Underpriced => "underpriced",
+            Self::InsufficientFunds { .. } => "insufficient_funds",
+            Self::IntrinsicGasTooLow { .. } => "intrinsic_gas_too_low",
+            Self::ExceedsBlockGasLimit => "exceeds_block_gas_limit",
+            Self::ExceedsFeeCap => "exceeds_fee_cap",
+            Self::TxPoolFull => "tx_pool_full",
+            Self::FeeCapBelowTip => "fee_cap_below_tip",
+            Self::ChainIdMismatch { .. } => "chain_id_mismatch",
+            Self::NotReplayProtected => "not_replay_protected",
+            Self::TxTypeNotSupported => "tx_type_not_supported",
+            Self::OversizedData => "oversized_data",
+            Self::BlobFeeCapTooLow => "blob_fee_cap_too_low",
+            Self::TooManyBlobs => "too_many_blobs",
+            Self::BlobTxMissingBlobs => "blob_tx_missing_blobs",
+            Self::BlobPoolConflict => "blob_pool_conflict",
+        }
+    }
+}
+
+/// `Some(1)` as ` 1`, nothing for `None`
+fn opt<T: std::fmt::Display>(label: &str, value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(|v| format!(" {} {}", label, v))
+        .unwrap_or_default()
+}
+
+impl std::fmt::Display for SubmitTxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::JRErr(e) => std::fmt::Display::fmt(e, f),
+            Self::NonceTooLow => write!(f, "nonce too low"),
+            Self::ReplacementUnderpriced => write!(f, "replacement transaction underpriced"),
+            Self::BaseGasPriceTooLow(msg) => write!(f, "max fee per gas below base fee: {}", msg),
+            Self::NonceTooHigh { nonce, expected } => write!(
+                f,
+                "nonce too high,{}{}",
+                opt("nonce", nonce),
+                opt("expected", expected)
+            ),
+            Self::AlreadyKnown => write!(f, "transaction already known"),
+            Self::Underpriced => write!(f, "transaction underpriced"),
+            Self::InsufficientFunds { have, want } => write!(
+                f,
+                "insufficient funds,{}{}",
+                opt("have", have),
+                opt("want", want)
+            ),
+            Self::IntrinsicGasTooLow { have, want } => write!(
+                f,
+                "intrinsic gas too low,{}{}",
+                opt("have", have),
+                opt("want", want)
+            ),
+            Self::ExceedsBlockGasLimit => write!(f, "exceeds block gas limit"),
+            Self::ExceedsFeeCap => write!(f, "tx fee exceeds the node's cap"),
+            Self::TxPoolFull => write!(f, "tx pool is full"),
+            Self::FeeCapBelowTip => write!(f, "max priority fee per gas higher than max fee per gas"),
+            Self::ChainIdMismatch { have, want } => write!(
+                f,
+                "chain id mismatch,{}{}",
+                opt("have", have),
+                opt("want", want)
+            ),
+            Self::NotReplayProtected => write!(f, "transaction is not replay protected (EIP-155)"),
+            Self::TxTypeNotSupported => write!(f, "transaction type not supported"),
+            Self::OversizedData => write!(f, "transaction too large"),
+            Self::BlobFeeCapTooLow => write!(f, "max fee per blob gas below blob base fee"),
+            Self::TooManyBlobs => write!(f, "too many blobs"),
+            Self::BlobTxMissingBlobs => write!(f, "blob transaction without blobs"),
+            Self::BlobPoolConflict => {
+                write!(f, "sender has pending transactions of the other pool (blob vs. non blob)")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubmitTxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::JRErr(e) => std::error::Error::source(e),
+            _ => None,
+        }
+    }
+}
+
+impl EthRpc {
+    pub fn send_raw_tx(&self, bytes: Bytes) -> Result<H256, SubmitTxError> {
+        let payload = JRCall::new("eth_sendRawTransaction", vec![bytes])
+            .map_err(SubmitTxError::JRErr)?;
+        // only retried if rejected before reaching the node, a resend after a timeout could land twice
+        self.call_single(payload).map_err(SubmitTxError::from)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::SafeJRResult;
+    use serde_json::{json, Value};
 
     #[test]
     fn test_get_nonce() {
@@ -55,4 +334,87 @@ mod test {
             .unwrap();
         assert_eq!(res, 2199);
     }
+
+    fn classify(error: Value) -> SubmitTxError {
+        SafeJRResult::try_from(json!({"jsonrpc": "2.0", "id": 1, "error": error}))
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn test_classify() {
+        use SubmitTxError::*;
+        let cases: Vec<(&str, Value, fn(&SubmitTxError) -> bool)> = vec![
+            // geth, erigon, reth and the providers running them
+            ("geth", json!({"code": -32000, "message": "nonce too low: address 0x0000000000000000000000000000000000000001, tx: 3 state: 5"}), |e| matches!(e, NonceTooLow)),
+            ("geth", json!({"code": -32000, "message": "nonce too high: address 0x0000000000000000000000000000000000000001, tx: 9 state: 5"}), |e| matches!(e, NonceTooHigh { nonce: Some(9), expected: Some(5) })),
+            ("geth", json!({"code": -32000, "message": "replacement transaction underpriced"}), |e| matches!(e, ReplacementUnderpriced)),
+            ("geth", json!({"code": -32000, "message": "transaction underpriced: tip needed 1000000000, tip permitted 1"}), |e| matches!(e, Underpriced)),
+            ("geth", json!({"code": -32000, "message": "already known"}), |e| matches!(e, AlreadyKnown)),
+            ("geth", json!({"code": -32000, "message": "insufficient funds for gas * price + value: address 0x0000000000000000000000000000000000000001 have 1000 want 2000"}), |e| matches!(e, InsufficientFunds { have: Some(h), want: Some(w) } if *h == 1000.into() && *w == 2000.into())),
+            ("geth", json!({"code": -32000, "message": "intrinsic gas too low: have 21000, want 53000"}), |e| matches!(e, IntrinsicGasTooLow { have: Some(21000), want: Some(53000) })),
+            ("geth", json!({"code": -32000, "message": "exceeds block gas limit"}), |e| matches!(e, ExceedsBlockGasLimit)),
+            ("geth", json!({"code": -32000, "message": "txpool is full"}), |e| matches!(e, TxPoolFull)),
+            ("geth", json!({"code": -32000, "message": "max priority fee per gas higher than max fee per gas: address 0x0000000000000000000000000000000000000001, maxPriorityFeePerGas: 2, maxFeePerGas: 1"}), |e| matches!(e, FeeCapBelowTip)),
+            ("geth", json!({"code": -32000, "message": "max fee per gas less than block base fee: address 0x0000000000000000000000000000000000000001, maxFeePerGas: 1, baseFee: 7"}), |e| matches!(e, BaseGasPriceTooLow(_))),
+            ("geth", json!({"code": -32000, "message": "invalid chain id for signer: have 5 want 1"}), |e| matches!(e, ChainIdMismatch { have: Some(5), want: Some(1) })),
+            ("geth", json!({"code": -32000, "message": "only replay-protected (EIP-155) transactions allowed over RPC"}), |e| matches!(e, NotReplayProtected)),
+            ("geth", json!({"code": -32000, "message": "tx fee (1.50 ether) exceeds the configured cap (1.00 ether)"}), |e| matches!(e, ExceedsFeeCap)),
+            ("geth", json!({"code": -32000, "message": "transaction type not supported"}), |e| matches!(e, TxTypeNotSupported)),
+            ("geth", json!({"code": -32000, "message": "oversized data: transaction size 200000, limit 131072"}), |e| matches!(e, OversizedData)),
+            ("geth", json!({"code": -32000, "message": "max fee per blob gas less than block blob gas fee: address 0x0000000000000000000000000000000000000001 blobGasFeeCap: 1, blobBaseFee: 2"}), |e| matches!(e, BlobFeeCapTooLow)),
+            ("geth", json!({"code": -32000, "message": "too many blobs in transaction: have 7, permitted 6"}), |e| matches!(e, TooManyBlobs)),
+            ("geth", json!({"code": -32000, "message": "blobless blob transaction"}), |e| matches!(e, BlobTxMissingBlobs)),
+            ("geth", json!({"code": -32000, "message": "address already reserved"}), |e| matches!(e, BlobPoolConflict)),
+            ("erigon", json!({"code": -32000, "message": "fee cap less than block base fee: address 0x0000000000000000000000000000000000000001, gasFeeCap: 1 baseFee: 7"}), |e| matches!(e, BaseGasPriceTooLow(_))),
+            ("erigon", json!({"code": -32000, "message": "fee too low"}), |e| matches!(e, Underpriced)),
+            ("reth", json!({"code": -32003, "message": "insufficient funds for gas * price + value: have 0 want 21000"}), |e| matches!(e, InsufficientFunds { have: Some(h), .. } if h.is_zero())),
+            ("reth", json!({"code": -32000, "message": "blob transaction missing blob hashes"}), |e| matches!(e, BlobTxMissingBlobs)),
+            ("reth", json!({"code": -32000, "message": "invalid chain id"}), |e| matches!(e, ChainIdMismatch { have: None, want: None })),
+            // nethermind puts the AcceptTxResult name first
+            ("nethermind", json!({"code": -32010, "message": "AlreadyKnown"}), |e| matches!(e, AlreadyKnown)),
+            ("nethermind", json!({"code": -32010, "message": "OldNonce, Current nonce: 5, nonce of rejected tx: 3"}), |e| matches!(e, NonceTooLow)),
+            ("nethermind", json!({"code": -32010, "message": "NonceGap, Future nonce. Nonce: 9, current nonce: 5"}), |e| matches!(e, NonceTooHigh { .. })),
+            ("nethermind", json!({"code": -32010, "message": "InsufficientFunds, Account balance: 0, cumulative cost: 21000"}), |e| matches!(e, InsufficientFunds { .. })),
+            ("nethermind", json!({"code": -32010, "message": "FeeTooLow, MaxFeePerGas too low. MaxFeePerGas: 1, BaseFee: 7"}), |e| matches!(e, Underpriced)),
+            ("nethermind", json!({"code": -32010, "message": "ReplacementNotAllowed"}), |e| matches!(e, ReplacementUnderpriced)),
+            ("nethermind", json!({"code": -32010, "message": "GasLimitExceeded, Gas limit: 30000000, gas limit of rejected tx: 40000000"}), |e| matches!(e, ExceedsBlockGasLimit)),
+            ("nethermind", json!({"code": -32010, "message": "NotSupportedTxType"}), |e| matches!(e, TxTypeNotSupported)),
+            ("nethermind", json!({"code": -32010, "message": "PendingTxsOfConflictingType"}), |e| matches!(e, BlobPoolConflict)),
+            ("nethermind", json!({"code": -32010, "message": "MaxTxSizeExceeded"}), |e| matches!(e, OversizedData)),
+            // besu
+            ("besu", json!({"code": -32001, "message": "Nonce too low"}), |e| matches!(e, NonceTooLow)),
+            ("besu", json!({"code": -32000, "message": "Transaction nonce is too distant from current sender nonce"}), |e| matches!(e, NonceTooHigh { .. })),
+            ("besu", json!({"code": -32004, "message": "Upfront cost exceeds account balance"}), |e| matches!(e, InsufficientFunds { .. })),
+            ("besu", json!({"code": -32003, "message": "Intrinsic gas exceeds gas limit"}), |e| matches!(e, IntrinsicGasTooLow { .. })),
+            ("besu", json!({"code": -32000, "message": "Transaction gas limit exceeds block gas limit"}), |e| matches!(e, ExceedsBlockGasLimit)),
+            ("besu", json!({"code": -32000, "message": "Known transaction"}), |e| matches!(e, AlreadyKnown)),
+            ("besu", json!({"code": -32000, "message": "Replacement transaction underpriced"}), |e| matches!(e, ReplacementUnderpriced)),
+            ("besu", json!({"code": -32000, "message": "Gas price below configured minimum gas price"}), |e| matches!(e, Underpriced)),
+            ("besu", json!({"code": -32000, "message": "Gas price below current base fee"}), |e| matches!(e, BaseGasPriceTooLow(_))),
+            ("besu", json!({"code": -32000, "message": "Max priority fee per gas exceeds max fee per gas"}), |e| matches!(e, FeeCapBelowTip)),
+            ("besu", json!({"code": -32000, "message": "Wrong chainId"}), |e| matches!(e, ChainIdMismatch { .. })),
+            ("besu", json!({"code": -32000, "message": "Transaction fee cap exceeded"}), |e| matches!(e, ExceedsFeeCap)),
+            // openethereum, still behind some providers
+            ("openethereum", json!({"code": -32010, "message": "Transaction nonce is too low. Try incrementing the nonce."}), |e| matches!(e, NonceTooLow)),
+            ("openethereum", json!({"code": -32010, "message": "Transaction with the same hash was already imported."}), |e| matches!(e, AlreadyKnown)),
+            ("openethereum", json!({"code": -32010, "message": "Transaction gas price is too low. There is another transaction with same nonce in the queue. Try increasing the gas price or incrementing the nonce."}), |e| matches!(e, ReplacementUnderpriced)),
+            // unknown stays a json rpc error
+            ("geth", json!({"code": -32000, "message": "header not found"}), |e| matches!(e, JRErr(JRError::JsonRpcResultError(_)))),
+        ];
+        for (client, error, expected) in cases {
+            let e = classify(error.clone());
+            assert!(expected(&e), "{} {} -> {:?}", client, error, e);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let e = classify(json!({"code": -32000, "message": "insufficient funds for gas * price + value: have 1 want 2"}));
+        assert_eq!(e.kind(), "insufficient_funds");
+        assert_eq!(e.to_string(), "insufficient funds, have 1 want 2");
+        let e = classify(json!({"code": -32602, "message": "invalid argument 0"}));
+        assert_eq!(e.kind(), "json_rpc_error");
+        assert_eq!(e.to_string(), "json rpc error -32602: invalid argument 0");
+    }
 }
@@ -0,0 +1,179 @@
+use super::{EthRpc, GetLogsEvent, JRError, SafeJRResult};
+use crate::RpcTransport;
+use ethers::types::{Block, H160, H256};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{marker::PhantomData, sync::mpsc::Receiver, time::Duration};
+
+/// notifications of a single `eth_subscribe`, unsubscribes on drop
+///
+/// survives reconnects: the transport resubscribes on a fresh connection
+/// (notifications emitted while disconnected are lost)
+#[derive(Debug)]
+pub struct Subscription<T> {
+    rx: Receiver<Value>,
+    transport: RpcTransport,
+    handle: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Subscription<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    /// blocks until the next notification
+    pub fn recv(&self) -> Option<Result<T, JRError>> {
+        self.rx.recv().ok().map(parse)
+    }
+
+    /// `None` if nothing arrived in time
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<T, JRError>> {
+        self.rx.recv_timeout(timeout).ok().map(parse)
+    }
+
+    pub fn try_recv(&self) -> Option<Result<T, JRError>> {
+        self.rx.try_recv().ok().map(parse)
+    }
+}
+
+fn parse<T>(v: Value) -> Result<T, JRError>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    serde_json::from_value(v).map_err(JRError::ResponseDoesNotMatchType)
+}
+
+impl<T> Iterator for Subscription<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = Result<T, JRError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.transport.unsubscribe(self.handle)
+    }
+}
+
+impl EthRpc {
+    /// raw `eth_subscribe`, needs a persistent transport (`Ws` or `Ipc`)
+    pub fn subscribe<T>(&self, params: Value) -> Result<Subscription<T>, JRError> {
+        let (handle, rx, response) = self
+            .transport
+            .subscribe(params)
+            .map_err(JRError::Transport)?;
+        // constructed first so that dropping it on error cleans up
+        let sub = Subscription {
+            rx,
+            transport: self.transport.clone(),
+            handle,
+            _marker: PhantomData,
+        };
+        let response: Value =
+            serde_json::from_slice(response.as_slice()).map_err(JRError::ResponseNotJson)?;
+        let _: SafeJRResult = response.try_into()?;
+        Ok(sub)
+    }
+
+    pub fn subscribe_new_heads(&self) -> Result<Subscription<Block<H256>>, JRError> {
+        self.subscribe(json!(["newHeads"]))
+    }
+
+    /// unlike `get_logs` reorged logs are delivered again with `removed: true`
+    pub fn subscribe_logs(
+        &self,
+        topics: Vec<H256>,
+        address: H160,
+    ) -> Result<Subscription<GetLogsEvent>, JRError> {
+        self.subscribe(json!(["logs", {"address": address, "topics": topics}]))
+    }
+
+    /// hashes only
+    pub fn subscribe_pending_txs(&self) -> Result<Subscription<H256>, JRError> {
+        self.subscribe(json!(["newPendingTransactions"]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::IpcConfig;
+    use std::{io::Write, os::unix::net::UnixListener};
+
+    fn head(number: u64) -> Value {
+        json!({
+            "parentHash": H256::zero(),
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "miner": "0x4838b106fce9647bdf1e7877bf73ce8b0bad5f97",
+            "stateRoot": H256::zero(),
+            "transactionsRoot": H256::zero(),
+            "receiptsRoot": H256::zero(),
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "difficulty": "0x0",
+            "number": format!("{:#x}", number),
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "timestamp": "0x64b4f6e3",
+            "extraData": "0x",
+            "mixHash": H256::zero(),
+            "nonce": "0x0000000000000000",
+            "baseFeePerGas": "0x7",
+            "hash": H256::from_low_u64_be(number)
+        })
+    }
+
+    /// every connection gets subscription `0x{n}` and two heads, then is dropped
+    fn mock_node() -> String {
+        let path = std::env::temp_dir().join(format!("eth_rpc_sub_{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            for (n, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let req: Value = serde_json::Deserializer::from_reader(&stream)
+                    .into_iter()
+                    .next()
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(req["method"], "eth_subscribe");
+                assert_eq!(req["params"], json!(["newHeads"]));
+                let sub_id = format!("{:#x}", n);
+                let mut out =
+                    json!({"jsonrpc": "2.0", "id": req["id"], "result": sub_id}).to_string();
+                for i in 0..2 {
+                    out += &json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": {"subscription": sub_id, "result": head((n * 2 + i) as u64)}
+                    })
+                    .to_string();
+                }
+                stream.write_all(out.as_bytes()).unwrap();
+            }
+        });
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_subscribe_new_heads_resubscribes() {
+        let ipc = IpcConfig::new(mock_node(), 1_000);
+        let client = EthRpc::with_transport(RpcTransport::Ipc(ipc));
+        let heads = client.subscribe_new_heads().unwrap();
+        // two heads per connection, the mock hangs up in between
+        let numbers: Vec<u64> = heads
+            .take(6)
+            .map(|h| h.unwrap().number.unwrap().as_u64())
+            .collect();
+        assert_eq!(numbers, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_subscribe_unsupported() {
+        let client = EthRpc::with_http("http://127.0.0.1:8545").unwrap();
+        assert!(client.subscribe_pending_txs().is_err());
+    }
+}
@@ -1,5 +1,4 @@
 use super::{EthRpc, JRCall, JRError};
-use crate::SafeJRResult;
 use ethers::types::U256;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -37,14 +36,10 @@ impl EthRpc {
     /// node is syncing, some nodes wont tell you the truth tho for different blockchains
     pub fn is_syncing(&self) -> Result<SyncStatus, JRError> {
         let payload = JRCall::new("eth_syncing", vec![] as Vec<()>)?;
-        let request = payload.to_vec()?;
-        let request = request.as_slice();
         // if node crashed for instance you don't want to protect this request
-        let result: Value = self.__call_transport(request)?;
-        let safe_jr_result: SafeJRResult = result.try_into()?;
-        let value = safe_jr_result.result;
+        let value: Value = self.call_single_with(payload, false)?;
 
-        if let Ok(bool) = serde_json::from_value::<bool>(value) {
+        if let Value::Bool(bool) = value {
             return Ok(if bool {
                 SyncStatus::Syncing
             } else {
@@ -52,10 +47,6 @@ impl EthRpc {
             });
         }
 
-        let result: Value = self.__call_transport(request)?;
-        let safe_jr_result: SafeJRResult = result.try_into()?;
-        let value = safe_jr_result.result;
-
         let geth = serde_json::from_value::<GethSyncingRes>(value)
             .map_err(|e| JRError::ResponseDoesNotMatchType(e))?;
         Ok(SyncStatus::SyncingGeth(geth))
@@ -65,6 +56,20 @@ impl EthRpc {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::http_mock::recording;
+    use serde_json::json;
+
+    #[test]
+    fn test_geth_syncing() {
+        let (client, requests) = recording(|_| {
+            Ok(json!({"currentBlock": "0x110706e", "highestBlock": "0x110c8d0"}))
+        });
+        match client.is_syncing().unwrap() {
+            SyncStatus::SyncingGeth(res) => assert_eq!(res.highest_block, 0x110c8d0.into()),
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
 
     #[test]
     fn test_is_syncing() {
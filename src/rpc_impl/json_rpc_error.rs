@@ -0,0 +1,179 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// [EIP-1474](https://eips.ethereum.org/EIPS/eip-1474) error object of a json rpc response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonRpcError {
+    /// 0 if the node sent none
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+    /// as sent by the node
+    #[serde(skip)]
+    pub raw: Value,
+}
+
+impl JsonRpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    pub const INVALID_INPUT: i64 = -32000;
+    pub const RESOURCE_NOT_FOUND: i64 = -32001;
+    pub const RESOURCE_UNAVAILABLE: i64 = -32002;
+    pub const TRANSACTION_REJECTED: i64 = -32003;
+    pub const METHOD_NOT_SUPPORTED: i64 = -32004;
+    pub const LIMIT_EXCEEDED: i64 = -32005;
+    /// geth and most clients for reverts with data
+    pub const EXECUTION_REVERTED: i64 = 3;
+
+    pub fn is_invalid_params(&self) -> bool {
+        self.code == Self::INVALID_PARAMS
+    }
+
+    pub fn is_method_not_found(&self) -> bool {
+        self.code == Self::METHOD_NOT_FOUND || self.code == Self::METHOD_NOT_SUPPORTED
+    }
+
+    pub fn is_limit_exceeded(&self) -> bool {
+        self.code == Self::LIMIT_EXCEEDED
+    }
+
+    /// reverts without data come with the generic -32000 and only the message tells,
+    /// nethermind says so in `data` and hardhat in a nested error object
+    pub fn is_execution_reverted(&self) -> bool {
+        let text = self.text();
+        self.code == Self::EXECUTION_REVERTED
+            || text.contains("execution reverted")
+            || text.contains("reverted 0x")
+    }
+
+    pub fn is_resource_unavailable(&self) -> bool {
+        self.code == Self::RESOURCE_UNAVAILABLE
+    }
+
+    /// lowercase message and data, for matching client specific texts
+    pub(crate) fn text(&self) -> String {
+        let mut text = self.message.to_lowercase();
+        if let Some(data) = &self.data {
+            text += " ";
+            text += &match data {
+                Value::String(s) => s.to_lowercase(),
+                v => v.to_string().to_lowercase(),
+            };
+        }
+        text
+    }
+}
+
+impl std::fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "json rpc error {}: {}", self.code, self.message)?;
+        if let Some(data) = &self.data {
+            write!(f, " ({})", data)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
+/// lenient, some nodes send a bare string or leave out the code
+impl From<Value> for JsonRpcError {
+    fn from(raw: Value) -> Self {
+        let message = match (&raw, raw.get("message")) {
+            (_, Some(Value::String(m))) => m.clone(),
+            (Value::String(m), _) => m.clone(),
+            (v, _) => v.to_string(),
+        };
+        Self {
+            code: raw.get("code").and_then(Value::as_i64).unwrap_or_default(),
+            message,
+            data: raw.get("data").filter(|d| !d.is_null()).cloned(),
+            raw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{JRError, SafeJRResult};
+    use serde_json::json;
+
+    fn error(error: Value) -> JRError {
+        SafeJRResult::try_from(json!({"jsonrpc": "2.0", "id": 1, "error": error})).unwrap_err()
+    }
+
+    #[test]
+    fn test_parse() {
+        let raw = json!({
+            "code": 3,
+            "message": "execution reverted: Dai/insufficient-balance",
+            "data": "0x08c379a0"
+        });
+        let err = error(raw.clone());
+        assert!(matches!(err, JRError::JsonRpcResultError(ref v) if *v == raw));
+        match err.json_rpc_error() {
+            Some(e) => {
+                assert_eq!(e.code, 3);
+                assert_eq!(e.data, Some(json!("0x08c379a0")));
+                assert_eq!(e.raw, raw);
+                assert!(e.is_execution_reverted());
+                assert!(!e.is_invalid_params());
+            }
+            None => panic!("unexpected {:?}", err),
+        }
+        let e = JsonRpcError::from(json!("method handler crashed"));
+        assert_eq!(e.code, 0);
+        assert_eq!(e.message, "method handler crashed");
+        assert_eq!(e.data, None);
+    }
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (
+                json!({"code": -32602, "message": "invalid argument 0: hex string has length 3, want 40 for common.Address"}),
+                "invalid params",
+            ),
+            (
+                json!({"code": -32601, "message": "the method eth_foo does not exist/is not available"}),
+                "method not found",
+            ),
+            (
+                json!({"code": -32004, "message": "method not supported"}),
+                "method not found",
+            ),
+            (
+                json!({"code": -32005, "message": "daily request count exceeded, request rate limited"}),
+                "limit exceeded",
+            ),
+            (
+                json!({"code": -32000, "message": "execution reverted"}),
+                "execution reverted",
+            ),
+            (
+                json!({"code": -32002, "message": "resource unavailable"}),
+                "resource unavailable",
+            ),
+            (json!({"code": -32000, "message": "header not found"}), ""),
+        ];
+        for (raw, expected) in cases {
+            let e = JsonRpcError::from(raw);
+            let found = [
+                (e.is_invalid_params(), "invalid params"),
+                (e.is_method_not_found(), "method not found"),
+                (e.is_limit_exceeded(), "limit exceeded"),
+                (e.is_execution_reverted(), "execution reverted"),
+                (e.is_resource_unavailable(), "resource unavailable"),
+            ]
+            .iter()
+            .find(|(is, _)| *is)
+            .map(|(_, name)| *name)
+            .unwrap_or_default();
+            assert_eq!(found, expected, "{:?}", e);
+        }
+    }
+}
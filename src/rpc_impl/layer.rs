@@ -0,0 +1,277 @@
+use super::{EthRpc, JRCall, JRError, SafeJRResult};
+use serde_json::Value;
+use std::fmt::Debug;
+
+/// see `RpcRequest::is_idempotent`
+pub(crate) fn is_idempotent_method(method: &str) -> bool {
+    method != "eth_sendRawTransaction" && method != "eth_sendTransaction"
+}
+
+/// what travels through the layers
+#[derive(Debug, Clone)]
+pub enum RpcRequest {
+    Single(JRCall),
+    /// one chunk of `EthRpc::batch`
+    Batch(Vec<JRCall>),
+}
+
+impl RpcRequest {
+    pub fn calls(&self) -> &[JRCall] {
+        match self {
+            Self::Single(call) => std::slice::from_ref(call),
+            Self::Batch(calls) => calls,
+        }
+    }
+
+    /// `false` if resending could have an effect twice, e.g. a transaction landing after a timeout
+    pub fn is_idempotent(&self) -> bool {
+        self.calls().iter().all(|c| is_idempotent_method(&c.method))
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>, JRError> {
+        match self {
+            Self::Single(call) => call.to_vec(),
+            Self::Batch(calls) => serde_json::to_vec(calls).map_err(JRError::JRCallSerialize),
+        }
+    }
+}
+
+/// for a single call `errs` is always empty, its json rpc error comes back as `Err`
+#[derive(Debug, Default)]
+pub struct RpcResponse {
+    pub results: Vec<SafeJRResult>,
+    pub errs: Vec<JRError>,
+}
+
+/// middleware around every call `EthRpc` sends, e.g. signing, auditing, metrics or caching
+///
+/// pass the request on with `next.run(request)`, or don't to short circuit
+pub trait Layer: Debug + Send + Sync {
+    fn call(&self, request: &RpcRequest, next: Next<'_>) -> Result<RpcResponse, JRError>;
+}
+
+/// rest of the stack, can be run more than once (retries)
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    rpc: &'a EthRpc,
+    layers: &'a [&'a dyn Layer],
+}
+
+impl<'a> Next<'a> {
+    pub fn run(self, request: &RpcRequest) -> Result<RpcResponse, JRError> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.call(
+                request,
+                Next {
+                    rpc: self.rpc,
+                    layers,
+                },
+            ),
+            None => self.rpc.send_request(request),
+        }
+    }
+}
+
+impl EthRpc {
+    /// wraps all calls, the first added layer is the outermost
+    pub fn with_layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(std::sync::Arc::new(layer));
+        self
+    }
+
+    /// runs `request` through the layers, `retry_policy` is the innermost unless disabled
+    pub(crate) fn call_layers(&self, request: &RpcRequest) -> Result<RpcResponse, JRError> {
+        self.call_layers_with(request, !self.disable_ratelimit_protection)
+    }
+
+    fn call_layers_with(&self, request: &RpcRequest, retry: bool) -> Result<RpcResponse, JRError> {
+        let mut layers: Vec<&dyn Layer> = self.layers.iter().map(|l| l.as_ref()).collect();
+        if retry {
+            layers.push(&self.retry_policy);
+        }
+        Next {
+            rpc: self,
+            layers: &layers,
+        }
+        .run(request)
+    }
+
+    /// single call through the layers, unwrapped into the desired type
+    pub(crate) fn call_single<R>(&self, call: JRCall) -> Result<R, JRError>
+    where
+        R: for<'a> serde::Deserialize<'a>,
+    {
+        self.call_single_with(call, !self.disable_ratelimit_protection)
+    }
+
+    /// `call_single`, without the retry policy if `retry` is false
+    pub(crate) fn call_single_with<R>(&self, call: JRCall, retry: bool) -> Result<R, JRError>
+    where
+        R: for<'a> serde::Deserialize<'a>,
+    {
+        let res = self.call_layers_with(&RpcRequest::Single(call), retry)?;
+        match res.results.into_iter().next() {
+            Some(result) => result.try_deserialize(),
+            None => Err(JRError::EmptyResponse),
+        }
+    }
+
+    /// innermost, below all layers
+    fn send_request(&self, request: &RpcRequest) -> Result<RpcResponse, JRError> {
+        let bytes = request.to_vec()?;
+        let methods: Vec<&str> = request.calls().iter().map(|c| c.method.as_str()).collect();
+        match request {
+            RpcRequest::Single(_) => {
+                let result: Value = self.__call_transport(bytes.as_slice(), &methods)?;
+                Ok(RpcResponse {
+                    results: vec![result.try_into()?],
+                    errs: Vec::new(),
+                })
+            }
+            RpcRequest::Batch(_) => {
+                let (results, errs) = self.batch_chunk(bytes.as_slice(), &methods)?;
+                Ok(RpcResponse { results, errs })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{HttpErr, RpcTransport, RpcTransportErr, Transport};
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    /// fails the first `fail` requests with a network error, then answers with block 0x10
+    #[derive(Debug, Default)]
+    struct Node {
+        fail: usize,
+        calls: AtomicUsize,
+    }
+
+    impl Transport for Node {
+        fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.fail {
+                let e = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+                return Err(RpcTransportErr::Http(HttpErr::IO(e)));
+            }
+            let res = |call: &Value| json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x10"});
+            let call: Value = serde_json::from_slice(params).unwrap();
+            let res = match call.as_array() {
+                Some(calls) => Value::from_iter(calls.iter().map(res)),
+                None => res(&call),
+            };
+            Ok(serde_json::to_vec(&res).unwrap())
+        }
+    }
+
+    /// records what passes by
+    #[derive(Debug)]
+    struct Audit {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Layer for Audit {
+        fn call(&self, request: &RpcRequest, next: Next<'_>) -> Result<RpcResponse, JRError> {
+            let methods: Vec<&str> = request.calls().iter().map(|c| c.method.as_str()).collect();
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} > {}", self.name, methods.join(",")));
+            let res = next.run(request)?;
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} < {}", self.name, res.results.len()));
+            Ok(res)
+        }
+    }
+
+    /// answers `eth_chainId` itself
+    #[derive(Debug)]
+    struct ChainIdCache;
+
+    impl Layer for ChainIdCache {
+        fn call(&self, request: &RpcRequest, next: Next<'_>) -> Result<RpcResponse, JRError> {
+            match request {
+                RpcRequest::Single(call) if call.method == "eth_chainId" => Ok(RpcResponse {
+                    results: vec![SafeJRResult {
+                        result: json!("0x1"),
+                        id: call.id,
+                    }],
+                    errs: Vec::new(),
+                }),
+                _ => next.run(request),
+            }
+        }
+    }
+
+    #[test]
+    fn test_layer_order() {
+        let node = Arc::new(Node::default());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let client = EthRpc::with_transport(RpcTransport::Custom(node.clone()))
+            .with_layer(Audit {
+                name: "outer",
+                log: log.clone(),
+            })
+            .with_layer(ChainIdCache)
+            .with_layer(Audit {
+                name: "inner",
+                log: log.clone(),
+            });
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        let chain_id: String = client
+            .no_ratelimit_rpc(JRCall::new("eth_chainId", Vec::new() as Vec<()>).unwrap())
+            .unwrap();
+        assert_eq!(chain_id, "0x1");
+        let calls = (0..2)
+            .map(|i| JRCall::new_with_id("eth_blockNumber", Vec::new() as Vec<()>, i).unwrap())
+            .collect();
+        assert_eq!(client.batch(calls).unwrap().len(), 2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "outer > eth_blockNumber",
+                "inner > eth_blockNumber",
+                "inner < 1",
+                "outer < 1",
+                "outer > eth_chainId",
+                "outer < 1",
+                "outer > eth_blockNumber,eth_blockNumber",
+                "inner > eth_blockNumber,eth_blockNumber",
+                "inner < 2",
+                "outer < 2",
+            ]
+        );
+        // the cache answered the chain id
+        assert_eq!(node.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let node = Arc::new(Node {
+            fail: 1,
+            ..Default::default()
+        });
+        let client = EthRpc::with_transport(RpcTransport::Custom(node.clone()));
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        assert_eq!(node.calls.load(Ordering::SeqCst), 2);
+
+        let node = Arc::new(Node {
+            fail: 1,
+            ..Default::default()
+        });
+        let mut client = EthRpc::with_transport(RpcTransport::Custom(node));
+        client.disable_ratelimit_protection = true;
+        assert!(client
+            .get_block_number()
+            .unwrap_err()
+            .is_network_or_ratelimit());
+    }
+}
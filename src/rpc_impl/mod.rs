@@ -1,5 +1,7 @@
 mod batch;
+mod block_id;
 mod custom;
+mod engine;
 mod eth_block_number;
 mod eth_call;
 mod eth_estimate_gas;
@@ -12,13 +14,32 @@ mod eth_get_transaction_by_hash;
 mod eth_get_transaction_count;
 mod eth_get_transaction_receipt;
 mod eth_send_raw_tx;
+mod eth_subscribe;
 mod eth_syncing;
 mod jr_call;
+mod json_rpc_error;
+mod layer;
+mod rate_limited;
+mod retry;
+mod revert;
 mod rpc;
 mod safe_id;
+pub use block_id::BlockId;
 pub use custom::*;
+pub use engine::{PayloadBodyV1, Withdrawal};
+pub use eth_call::{
+    AccountOverride, BlockOverrides, CallHandle, CallOverrides, Multicall, MulticallError,
+    MulticallResults, TokenMetadata, DEFAULT_CALL_GAS, MULTICALL3,
+};
 pub use eth_get_logs::GetLogsEvent;
 pub use eth_send_raw_tx::SubmitTxError;
+pub use eth_subscribe::Subscription;
 pub use eth_syncing::SyncStatus;
 pub use jr_call::JRCall;
+pub use json_rpc_error::JsonRpcError;
+pub use layer::{Layer, Next, RpcRequest, RpcResponse};
+pub(crate) use layer::is_idempotent_method;
+pub use rate_limited::{add_rate_limit_rule, remove_rate_limit_rules, RateLimitRule};
+pub use retry::RetryPolicy;
+pub use revert::{PanicCode, RevertReason};
 pub use rpc::{EthRpc, JRError, SafeJRResult};
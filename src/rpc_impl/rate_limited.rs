@@ -0,0 +1,281 @@
+use super::{json_rpc_error::JsonRpcError, JRError};
+use crate::transport::RpcTransportErr;
+use std::{
+    borrow::Cow,
+    sync::{Mutex, OnceLock},
+};
+
+/// one provider's way of saying "slow down" or "try again later", all set fields have to match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitRule {
+    /// for logs only
+    pub provider: Cow<'static, str>,
+    /// of the http response, the body is not looked at
+    pub status: Option<u16>,
+    /// of the json rpc error object
+    pub code: Option<i64>,
+    /// lowercase, searched in the json rpc error's message and data
+    pub text: Option<Cow<'static, str>>,
+}
+
+const fn status(provider: &'static str, status: u16) -> RateLimitRule {
+    RateLimitRule {
+        provider: Cow::Borrowed(provider),
+        status: Some(status),
+        code: None,
+        text: None,
+    }
+}
+
+const fn error(
+    provider: &'static str,
+    code: Option<i64>,
+    text: Option<&'static str>,
+) -> RateLimitRule {
+    RateLimitRule {
+        provider: Cow::Borrowed(provider),
+        status: None,
+        code,
+        text: match text {
+            Some(text) => Some(Cow::Borrowed(text)),
+            None => None,
+        },
+    }
+}
+
+/// -32005 alone doesn't tell, infura also sends it for `eth_getLogs` over 10k results
+const RULES: &[RateLimitRule] = &[
+    status("any", 429),
+    status("any", 503),
+    status("any", 504),
+    // origin unreachable or timing out behind cloudflare
+    status("cloudflare", 520),
+    status("cloudflare", 522),
+    status("cloudflare", 524),
+    error("any", Some(429), None),
+    error("any", None, Some("too many requests")),
+    error("any", None, Some("rate limit")),
+    error("any", None, Some("try again later")),
+    error("alchemy", None, Some("exceeded its compute units")),
+    error("infura", Some(-32005), Some("rate")),
+    error("quicknode", Some(-32007), None),
+    error("quicknode", None, Some("request limit reached")),
+    error("ankr", None, Some("call rate limit exhausted")),
+    error("chainstack", None, Some("exceeded the rps limit")),
+    error("cloudflare", Some(-32046), Some("cannot fulfill request")),
+];
+
+fn custom_rules() -> &'static Mutex<Vec<RateLimitRule>> {
+    static RULES: OnceLock<Mutex<Vec<RateLimitRule>>> = OnceLock::new();
+    RULES.get_or_init(Default::default)
+}
+
+/// process wide, checked after the built in rules of the known providers
+pub fn add_rate_limit_rule(rule: RateLimitRule) {
+    custom_rules().lock().unwrap().push(rule);
+}
+
+/// drops the custom rules of `provider`, the built in ones stay
+pub fn remove_rate_limit_rules(provider: &str) {
+    custom_rules()
+        .lock()
+        .unwrap()
+        .retain(|r| r.provider != provider);
+}
+
+impl RateLimitRule {
+    pub fn new(provider: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            provider: provider.into(),
+            status: None,
+            code: None,
+            text: None,
+        }
+    }
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+    pub fn with_code(mut self, code: i64) -> Self {
+        self.code = Some(code);
+        self
+    }
+    pub fn with_text(mut self, text: impl Into<Cow<'static, str>>) -> Self {
+        self.text = Some(text.into().to_lowercase().into());
+        self
+    }
+
+    fn matches_status(&self, status: u16) -> bool {
+        self.status == Some(status) && self.code.is_none() && self.text.is_none()
+    }
+
+    /// texts are not matched against reverts, their reason is up to the contract
+    fn matches_error(&self, e: &JsonRpcError, text: &str) -> bool {
+        let reverted = e.code == JsonRpcError::EXECUTION_REVERTED
+            || e.message.to_lowercase().starts_with("execution reverted");
+        self.status.is_none()
+            && (self.code.is_some() || self.text.is_some())
+            && !(reverted && self.text.is_some())
+            && self.code.is_none_or(|code| code == e.code)
+            && self.text.as_ref().is_none_or(|t| text.contains(t.as_ref()))
+    }
+}
+
+/// the first rule matching, built in ones first
+fn find(pred: impl Fn(&RateLimitRule) -> bool) -> Option<Cow<'static, str>> {
+    if let Some(rule) = RULES.iter().find(|r| pred(r)) {
+        return Some(rule.provider.clone());
+    }
+    let custom = custom_rules().lock().unwrap();
+    custom.iter().find(|r| pred(r)).map(|r| r.provider.clone())
+}
+
+impl JsonRpcError {
+    /// provider that throttled the request, see `RateLimitRule`
+    pub fn rate_limited_by(&self) -> Option<Cow<'static, str>> {
+        let text = self.text();
+        find(|rule| rule.matches_error(self, &text))
+    }
+}
+
+impl RpcTransportErr {
+    /// http status that means throttled or over capacity, see `RateLimitRule`
+    pub fn is_http_rate_limited(&self) -> bool {
+        match self {
+            Self::Http(e) => e
+                .status()
+                .is_some_and(|status| find(|rule| rule.matches_status(*status)).is_some()),
+            _ => false,
+        }
+    }
+}
+
+impl JRError {
+    /// throttled or over capacity, worth retrying after a while
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            Self::RateLimited(_) | Self::Busy { .. } => true,
+            Self::Transport(e) => e.is_http_rate_limited(),
+            Self::JsonRpcResultError(_) => self
+                .json_rpc_error()
+                .is_some_and(|e| e.rate_limited_by().is_some()),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        EthRpc, HttpTransport, RpcTransport, SafeJRResult,
+    };
+    use serde_json::{json, Value};
+
+    fn error(error: Value) -> JRError {
+        SafeJRResult::try_from(json!({"jsonrpc": "2.0", "id": 1, "error": error})).unwrap_err()
+    }
+
+    #[test]
+    fn test_providers() {
+        let cases = [
+            (
+                "alchemy",
+                json!({"code": 429, "message": "Your app has exceeded its compute units per second capacity. If you have retries enabled, you can safely ignore this message. If not, check out https://docs.alchemy.com/reference/throughput"}),
+            ),
+            (
+                "infura",
+                json!({"code": -32005, "message": "daily request count exceeded, request rate limited", "data": {"rate": {"allowed_rps": 1, "backoff_seconds": 30, "current_rps": 1.4}, "see": "https://infura.io/dashboard"}}),
+            ),
+            (
+                "infura",
+                json!({"code": -32005, "message": "project ID request rate exceeded", "data": {"see": "https://infura.io/docs/ethereum/jsonrpc/ratelimits"}}),
+            ),
+            (
+                "quicknode",
+                json!({"code": -32007, "message": "100/second request limit reached - reduce calls per second or upgrade your account at quicknode.com"}),
+            ),
+            (
+                "ankr",
+                json!({"code": -32000, "message": "Too many requests, reason: call rate limit exhausted, retry in 10s"}),
+            ),
+            (
+                "chainstack",
+                json!({"code": -32000, "message": "You've exceeded the RPS limit available on the current plan."}),
+            ),
+            (
+                "cloudflare",
+                json!({"code": -32046, "message": "Cannot fulfill request"}),
+            ),
+            (
+                "llamarpc",
+                json!({"code": -32000, "message": "over rate limit"}),
+            ),
+            (
+                "public node",
+                json!({"code": -32603, "message": "server is overloaded, try again later"}),
+            ),
+        ];
+        for (provider, raw) in cases {
+            let err = error(raw.clone());
+            assert!(
+                matches!(err, JRError::RateLimited(_)),
+                "{} {}",
+                provider,
+                raw
+            );
+            assert!(err.is_network_or_ratelimit());
+        }
+
+        let not_limited = [
+            json!({"code": -32005, "message": "query returned more than 10000 results"}),
+            json!({"code": -32000, "message": "header not found"}),
+            json!({"code": 3, "message": "execution reverted"}),
+            json!({"code": 3, "message": "execution reverted: rate limit exceeded", "data": "0x"}),
+            json!({"code": -32000, "message": "execution reverted: too many requests"}),
+            json!({"code": -32000, "message": "nonce too low"}),
+        ];
+        for raw in not_limited {
+            let err = error(raw.clone());
+            assert!(matches!(err, JRError::JsonRpcResultError(_)), "{}", raw);
+            assert!(!err.is_network_or_ratelimit(), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        for (status, limited) in [
+            (429, true),
+            (503, true),
+            (524, true),
+            (401, false),
+            (500, false),
+        ] {
+            let url = serve(move |_| MockResponse::status(status, "error code: 1015"));
+            let mut client =
+                EthRpc::with_transport(RpcTransport::Http(HttpTransport::new(&url).unwrap()));
+            client.disable_ratelimit_protection = true;
+            let err = client.get_block_number().unwrap_err();
+            assert_eq!(err.is_rate_limited(), limited, "{}", status);
+            assert_eq!(err.is_network_or_ratelimit(), limited, "{}", status);
+        }
+    }
+
+    #[test]
+    fn test_custom_rule() {
+        let raw = json!({"code": -32099, "message": "Slow Down, Partner"});
+        assert!(!error(raw.clone()).is_rate_limited());
+        add_rate_limit_rule(
+            RateLimitRule::new("my gateway")
+                .with_code(-32099)
+                .with_text("Slow down"),
+        );
+        let err = error(raw);
+        assert!(matches!(err, JRError::RateLimited(_)));
+        let e = JsonRpcError::from(json!({"code": -32099, "message": "slow down"}));
+        assert_eq!(e.rate_limited_by().as_deref(), Some("my gateway"));
+        remove_rate_limit_rules("my gateway");
+        assert_eq!(e.rate_limited_by(), None);
+    }
+}
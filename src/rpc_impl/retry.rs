@@ -0,0 +1,279 @@
+use super::{
+    layer::{Layer, Next, RpcRequest, RpcResponse},
+    EthRpc, JRError,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// exponential backoff for network errors and rate limits, the innermost layer of every call.
+///
+/// a `Retry-After` sent with a 429 or 503 is waited out even if longer than the backoff.
+/// transactions are only resent if the node surely never got them
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// first try included
+    pub max_attempts: u32,
+    /// before the first retry
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+    /// the delay grows by this factor with every retry
+    pub factor: f64,
+    /// sleeps a random time between 0 and the delay so that clients hitting
+    /// the same rate limit don't retry in lockstep
+    pub jitter: bool,
+    /// gives up instead of sleeping past this time since the first attempt
+    pub deadline_millis: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_millis: 1_000,
+            max_delay_millis: 10_000,
+            factor: 2.0,
+            jitter: true,
+            deadline_millis: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// no retries at all
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// backoff before retry number `retry`, counting from 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        let millis = (self.base_delay_millis as f64 * self.factor.powi(retry as i32))
+            .min(self.max_delay_millis as f64)
+            .max(0.0);
+        let millis = if self.jitter && millis > 0.0 {
+            rand::thread_rng().gen_range(0.0..=millis)
+        } else {
+            millis
+        };
+        Duration::from_millis(millis as u64)
+    }
+
+    fn should_retry(&self, request: &RpcRequest, e: &JRError) -> bool {
+        if request.is_idempotent() {
+            e.is_network_or_ratelimit()
+        } else {
+            e.is_rejected()
+        }
+    }
+}
+
+impl EthRpc {
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+}
+
+impl Layer for RetryPolicy {
+    fn call(&self, request: &RpcRequest, next: Next<'_>) -> Result<RpcResponse, JRError> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let e = match next.run(request) {
+                Ok(res) => return Ok(res),
+                Err(e) => e,
+            };
+            if !self.should_retry(request, &e) {
+                return Err(e);
+            }
+            if attempt >= self.max_attempts {
+                error!("Retries fail: {:?}", e);
+                return Err(e);
+            }
+            let mut delay = self.delay(attempt - 1);
+            if let Some(after) = e.retry_after() {
+                delay = delay.max(after);
+            }
+            if let Some(deadline) = self.deadline_millis {
+                if start.elapsed() + delay > Duration::from_millis(deadline) {
+                    error!("Retry deadline exceeded: {:?}", e);
+                    return Err(e);
+                }
+            }
+            warn!("Network err or rate limited, retrying in {:?}", delay);
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        HttpErr, HttpTransport, RpcTransport, RpcTransportErr, Transport,
+    };
+    use ethers::types::{Bytes, H256};
+    use serde_json::{json, Value};
+    use std::{
+        io::ErrorKind,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    /// fails with the queued io errors first, then answers
+    #[derive(Debug, Default)]
+    struct Flaky {
+        errs: Mutex<Vec<ErrorKind>>,
+        calls: AtomicUsize,
+    }
+
+    impl Transport for Flaky {
+        fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(kind) = self.errs.lock().unwrap().pop() {
+                return Err(RpcTransportErr::Http(HttpErr::IO(kind.into())));
+            }
+            let call: Value = serde_json::from_slice(params).unwrap();
+            let result = match call["method"].as_str() {
+                Some("eth_sendRawTransaction") => json!(H256::zero()),
+                _ => json!("0x10"),
+            };
+            let res = json!({"jsonrpc": "2.0", "id": call["id"], "result": result});
+            Ok(serde_json::to_vec(&res).unwrap())
+        }
+    }
+
+    fn flaky_client(errs: Vec<ErrorKind>, policy: RetryPolicy) -> (EthRpc, Arc<Flaky>) {
+        let node = Arc::new(Flaky {
+            errs: Mutex::new(errs),
+            ..Default::default()
+        });
+        let client = EthRpc::with_transport(RpcTransport::Custom(node.clone()));
+        (client.with_retry_policy(policy), node)
+    }
+
+    fn fast() -> RetryPolicy {
+        RetryPolicy {
+            base_delay_millis: 1,
+            max_delay_millis: 5,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (0..5).map(|i| policy.delay(i).as_millis() as u64).collect();
+        assert_eq!(delays, vec![1_000, 2_000, 4_000, 8_000, 10_000]);
+        let policy = RetryPolicy::default();
+        for i in 0..5 {
+            assert!(policy.delay(i) <= Duration::from_millis(10_000));
+        }
+        let config: RetryPolicy =
+            serde_json::from_value(json!({"max_attempts": 3, "deadline_millis": 500})).unwrap();
+        assert_eq!(config.factor, 2.0);
+        assert_eq!(config.deadline_millis, Some(500));
+    }
+
+    #[test]
+    fn test_max_attempts_and_deadline() {
+        let (client, node) = flaky_client(vec![ErrorKind::ConnectionReset; 10], fast());
+        assert!(client.get_block_number().is_err());
+        assert_eq!(node.calls.load(Ordering::SeqCst), 5);
+
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            base_delay_millis: 20,
+            jitter: false,
+            deadline_millis: Some(100),
+            ..Default::default()
+        };
+        let (client, node) = flaky_client(vec![ErrorKind::ConnectionReset; 100], policy);
+        let start = Instant::now();
+        assert!(client.get_block_number().is_err());
+        assert!(start.elapsed() <= Duration::from_millis(100));
+        // 20 + 40 and the next 80 would overshoot
+        assert_eq!(node.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let inner = hits.clone();
+        let url = serve(move |req| {
+            if inner.fetch_add(1, Ordering::SeqCst) == 0 {
+                return MockResponse::status(429, "slow down").header("retry-after", "1");
+            }
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"}))
+        });
+        let client = EthRpc::with_transport(RpcTransport::Http(HttpTransport::new(&url).unwrap()))
+            .with_retry_policy(fast());
+        let start = Instant::now();
+        assert_eq!(client.get_block_number().unwrap(), 1);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_busy_status() {
+        for (status, resent) in [(429, true), (503, false)] {
+            let hits = Arc::new(AtomicUsize::new(0));
+            let inner = hits.clone();
+            let url = serve(move |req| {
+                if inner.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return MockResponse::status(status, "busy");
+                }
+                let call: Value = serde_json::from_slice(&req.body).unwrap();
+                MockResponse::ok(
+                    json!({"jsonrpc": "2.0", "id": call["id"], "result": H256::zero()}),
+                )
+            });
+            let http = RpcTransport::Http(HttpTransport::new(&url).unwrap());
+            let client = EthRpc::with_transport(http.clone()).with_retry_policy(fast());
+            assert_eq!(client.send_raw_tx(Bytes::default()).is_ok(), resent);
+            assert_eq!(hits.load(Ordering::SeqCst), if resent { 2 } else { 1 });
+
+            let client = EthRpc::with_transport(http).with_retry_policy(RetryPolicy::none());
+            hits.store(0, Ordering::SeqCst);
+            match client.get_block_number().unwrap_err() {
+                JRError::Busy {
+                    status: s,
+                    retry_after: None,
+                } => assert_eq!(s, status),
+                e => panic!("unexpected {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_send_raw_tx() {
+        // may have reached the node
+        let (client, node) = flaky_client(vec![ErrorKind::TimedOut], fast());
+        assert!(client.send_raw_tx(Bytes::default()).is_err());
+        assert_eq!(node.calls.load(Ordering::SeqCst), 1);
+        // surely didn't
+        let (client, node) = flaky_client(vec![ErrorKind::ConnectionRefused], fast());
+        assert_eq!(client.send_raw_tx(Bytes::default()).unwrap(), H256::zero());
+        assert_eq!(node.calls.load(Ordering::SeqCst), 2);
+        // reads are retried either way
+        let (client, node) = flaky_client(vec![ErrorKind::TimedOut], fast());
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        assert_eq!(node.calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,256 @@
+use super::{json_rpc_error::JsonRpcError, JRError};
+use ethers::{
+    abi::AbiDecode,
+    types::{Bytes, U256},
+};
+use serde_json::Value;
+
+/// selector of `Error(string)`
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// selector of `Panic(uint256)`
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    /// `require(cond, "reason")` or `revert("reason")`
+    Error(String),
+    /// `assert`, overflows, division by zero..
+    Panic(PanicCode),
+    /// custom error or garbage, decode it with `JRError::decode_revert`
+    Custom,
+    /// `revert()` or a node that doesn't send the data
+    Empty,
+}
+
+/// solidity's panic codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicCode {
+    Generic,
+    AssertFailed,
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidEnumValue,
+    InvalidStorageByteArray,
+    PopOnEmptyArray,
+    IndexOutOfBounds,
+    OutOfMemory,
+    InvalidInternalFunction,
+    Unknown(U256),
+}
+
+impl From<U256> for PanicCode {
+    fn from(code: U256) -> Self {
+        if code > U256::from(0xff) {
+            return Self::Unknown(code);
+        }
+        match code.as_u32() {
+            0x00 => Self::Generic,
+            0x01 => Self::AssertFailed,
+            0x11 => Self::ArithmeticOverflow,
+            0x12 => Self::DivisionByZero,
+            0x21 => Self::InvalidEnumValue,
+            0x22 => Self::InvalidStorageByteArray,
+            0x31 => Self::PopOnEmptyArray,
+            0x32 => Self::IndexOutOfBounds,
+            0x41 => Self::OutOfMemory,
+            0x51 => Self::InvalidInternalFunction,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+impl std::fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error(reason) => write!(f, "{}", reason),
+            Self::Panic(code) => write!(f, "panic {:?}", code),
+            Self::Custom => write!(f, "custom error"),
+            Self::Empty => write!(f, "no reason"),
+        }
+    }
+}
+
+impl RevertReason {
+    pub fn decode(raw: &[u8]) -> Self {
+        if raw.is_empty() {
+            return Self::Empty;
+        }
+        if raw.len() >= 4 {
+            let (selector, args) = raw.split_at(4);
+            if selector == ERROR_SELECTOR {
+                if let Ok(reason) = String::decode(args) {
+                    return Self::Error(reason);
+                }
+            }
+            if selector == PANIC_SELECTOR {
+                if let Ok(code) = U256::decode(args) {
+                    return Self::Panic(code.into());
+                }
+            }
+        }
+        Self::Custom
+    }
+}
+
+impl JsonRpcError {
+    /// revert data as sent by geth (`data: "0x.."`), nethermind (`data: "Reverted 0x.."`)
+    /// or hardhat and ganache (`data: {"data": "0x.."}`), `None` if not a revert
+    pub fn revert_data(&self) -> Option<Bytes> {
+        if !self.is_execution_reverted() {
+            return None;
+        }
+        Some(self.data.as_ref().and_then(find_hex).unwrap_or_default())
+    }
+}
+
+fn find_hex(data: &Value) -> Option<Bytes> {
+    match data {
+        Value::String(s) => s.trim_start_matches("Reverted ").parse().ok(),
+        Value::Object(map) => map
+            .get("data")
+            .and_then(find_hex)
+            .or_else(|| map.values().find_map(find_hex)),
+        _ => None,
+    }
+}
+
+impl JRError {
+    /// `JsonRpcResultError` of a revert into `Reverted`, everything else as is
+    pub(crate) fn decode_reverted(self) -> Self {
+        let e = match self.json_rpc_error() {
+            Some(e) => e,
+            None => return self,
+        };
+        let raw = match e.revert_data() {
+            Some(raw) => raw,
+            None => return self,
+        };
+        let mut reason = RevertReason::decode(&raw);
+        // nodes dropping the data usually keep the reason in the message
+        if reason == RevertReason::Empty {
+            if let Some((_, msg)) = e.message.split_once("execution reverted: ") {
+                reason = RevertReason::Error(msg.to_string());
+            }
+        }
+        Self::Reverted { reason, raw }
+    }
+
+    /// the revert data as your abigen error enum (`..Errors`) or any other `AbiDecode`
+    pub fn decode_revert<E: AbiDecode>(&self) -> Option<E> {
+        match self {
+            Self::Reverted { raw, .. } => E::decode(raw).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http_mock::recording, EthRpc};
+    use ethers::{
+        abi::{AbiEncode, Token},
+        prelude::abigen,
+        types::{transaction::eip2718::TypedTransaction, H160},
+    };
+    use serde_json::json;
+
+    abigen!(
+        Vault,
+        r#"[
+        function withdraw(uint256 amount) external
+        error InsufficientBalance(uint256 available, uint256 required)
+        error Paused()
+    ]"#
+    );
+
+    fn encode(selector: [u8; 4], token: Token) -> Bytes {
+        let mut raw = selector.to_vec();
+        raw.extend(ethers::abi::encode(&[token]));
+        raw.into()
+    }
+
+    fn client(error: Value) -> EthRpc {
+        recording(move |_| Err(error.clone())).0
+    }
+
+    #[test]
+    fn test_decode() {
+        let error = encode(
+            ERROR_SELECTOR,
+            Token::String("Dai/insufficient-balance".into()),
+        );
+        assert_eq!(
+            RevertReason::decode(&error),
+            RevertReason::Error("Dai/insufficient-balance".into())
+        );
+        let cases = [
+            (0x01, PanicCode::AssertFailed),
+            (0x11, PanicCode::ArithmeticOverflow),
+            (0x12, PanicCode::DivisionByZero),
+            (0x32, PanicCode::IndexOutOfBounds),
+            (0x99, PanicCode::Unknown(0x99.into())),
+        ];
+        for (code, expected) in cases {
+            let panic = encode(PANIC_SELECTOR, Token::Uint(code.into()));
+            assert_eq!(RevertReason::decode(&panic), RevertReason::Panic(expected));
+        }
+        assert_eq!(RevertReason::decode(&[]), RevertReason::Empty);
+        assert_eq!(RevertReason::decode(&[1, 2]), RevertReason::Custom);
+    }
+
+    #[test]
+    fn test_reverted() {
+        let custom = InsufficientBalance {
+            available: 1.into(),
+            required: 2.into(),
+        };
+        let raw = Bytes::from(custom.clone().encode());
+        let error_string = encode(ERROR_SELECTOR, Token::String("nope".into()));
+        let cases = vec![
+            // geth, erigon, reth
+            json!({"code": 3, "message": "execution reverted", "data": raw}),
+            // nethermind
+            json!({"code": -32015, "message": "VM execution error.", "data": format!("Reverted {}", raw)}),
+            // hardhat
+            json!({"code": -32603, "message": "Error: VM Exception while processing transaction: reverted with custom error", "data": {"message": "execution reverted", "data": raw}}),
+        ];
+        for error in cases {
+            let mut tx = TypedTransaction::default();
+            tx.set_to(H160::zero());
+            let err = client(error).eth_call(tx).unwrap_err();
+            match &err {
+                JRError::Reverted { reason, raw: r } => {
+                    assert_eq!(reason, &RevertReason::Custom);
+                    assert_eq!(r, &raw);
+                }
+                e => panic!("unexpected {:?}", e),
+            }
+            assert_eq!(
+                err.decode_revert::<VaultErrors>(),
+                Some(VaultErrors::InsufficientBalance(custom.clone()))
+            );
+        }
+
+        let err =
+            client(json!({"code": 3, "message": "execution reverted: nope", "data": error_string}))
+                .eth_call_typed::<U256>(H160::zero(), WithdrawCall { amount: 1.into() })
+                .unwrap_err();
+        assert!(
+            matches!(err, JRError::Reverted { reason: RevertReason::Error(r), .. } if r == "nope")
+        );
+
+        // old infura, no data but the reason in the message
+        let err = client(json!({"code": -32000, "message": "execution reverted: nope"}))
+            .estimate_gas(TypedTransaction::default())
+            .unwrap_err();
+        assert!(
+            matches!(err, JRError::Reverted { reason: RevertReason::Error(r), .. } if r == "nope")
+        );
+
+        let err = client(json!({"code": -32000, "message": "header not found"}))
+            .estimate_gas(TypedTransaction::default())
+            .unwrap_err();
+        assert!(matches!(err, JRError::JsonRpcResultError(_)));
+    }
+}
@@ -1,13 +1,16 @@
-use super::jr_call::JRCall;
+use super::{
+    jr_call::JRCall, json_rpc_error::JsonRpcError, layer::Layer, retry::RetryPolicy,
+    revert::RevertReason,
+};
 use crate::{
     transport::{RpcTransport, RpcTransportErr},
-    EnvHttp, HttpErr,
+    CircuitState, EndpointStats, EnvHttp, HttpErr, NoQuorum, SecretUrl,
 };
+use ethers::types::Bytes;
 
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use std::{fmt::Debug, ops::Deref, time::Duration};
-use tracing::{error, warn};
+use serde_json::Value;
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct JRRes {
@@ -42,13 +45,12 @@ impl TryFrom<Value> for SafeJRResult {
 
         // check if result has error
         if let Some(e) = result.error {
-            // catch alchemy ratelimit
-            if let Ok(s) = serde_json::to_string(&e) {
-                if s.contains("exceeded its compute units") {
-                    return Err(JRError::RateLimited(e));
-                }
+            let e = JsonRpcError::from(e);
+            // throttling of the known providers, see `RateLimitRule`
+            if e.rate_limited_by().is_some() {
+                return Err(JRError::RateLimited(e.raw));
             }
-            return Err(JRError::JsonRpcResultError(e));
+            return Err(JRError::JsonRpcResultError(e.raw));
         }
         // check if result exists
         if result.result.is_none() {
@@ -66,8 +68,15 @@ impl TryFrom<Value> for SafeJRResult {
 pub struct EthRpc {
     pub transport: RpcTransport,
     pub batch_chunk_size: Option<usize>,
+    /// turns off `retry_policy`
     #[serde(default)]
     pub disable_ratelimit_protection: bool,
+    /// see `with_retry_policy`
+    #[serde(default)]
+    pub(crate) retry_policy: RetryPolicy,
+    /// outermost first, see `with_layer`
+    #[serde(skip)]
+    pub(crate) layers: Vec<Arc<dyn Layer>>,
 }
 
 #[derive(Debug)]
@@ -79,32 +88,164 @@ pub enum JRError {
     ResponseNotJson(serde_json::Error),
     ResponseNotJsonRpcResponse(serde_json::Error),
     /// not guaranteed to catch the ratelimit error
-    /// it might masquarade as some other error here
+    /// it might masquarade as some other error here.
+    /// the node's error object, see `RateLimitRule`
     RateLimited(Value),
+    /// answered with 429, 503 or another throttling status of a `RateLimitRule`,
+    /// `retry_after` if the server sent one
+    Busy {
+        status: u16,
+        retry_after: Option<Duration>,
+    },
 
     ResponseDoesNotMatchType(serde_json::Error),
 
+    /// the node's error object as sent, parsed by `json_rpc_error`
     JsonRpcResultError(Value),
     BatchMissingResponses,
     EmptyResponse,
+    /// lists the endpoints that disagreed with the majority
+    NoQuorum(NoQuorum),
+    /// the endpoint's circuit breaker is open, nothing was sent
+    CircuitOpen(SecretUrl),
+    /// of `eth_call` or `estimate_gas`, `raw` is the revert data, empty if the node sent none
+    Reverted {
+        reason: RevertReason,
+        raw: Bytes,
+    },
 
     Extension(String),
 }
 
 impl JRError {
+    /// stable name for grouping in logs and alerts, transport errors name the inner error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::FailInitialize(_) => "fail_initialize",
+            Self::JRCallSerialize(_) => "call_serialize",
+            Self::Transport(e) => e.kind(),
+            Self::ResponseNotUtf8(_) => "response_not_utf8",
+            Self::ResponseNotJson(_) => "response_not_json",
+            Self::ResponseNotJsonRpcResponse(_) => "response_not_json_rpc",
+            Self::RateLimited(_) => "rate_limited",
+            Self::Busy { .. } => "busy",
+            Self::ResponseDoesNotMatchType(_) => "response_type_mismatch",
+            Self::JsonRpcResultError(_) => "json_rpc_error",
+            Self::BatchMissingResponses => "batch_missing_responses",
+            Self::EmptyResponse => "empty_response",
+            Self::NoQuorum(_) => "no_quorum",
+            Self::CircuitOpen(_) => "circuit_open",
+            Self::Reverted { .. } => "reverted",
+            Self::Extension(_) => "extension",
+        }
+    }
     pub fn is_network_or_ratelimit(&self) -> bool {
-        if matches!(self, Self::RateLimited(_)) {
+        if self.is_rate_limited() {
             return true;
         }
         if let Self::Transport(e) = &self {
             // failed in network io
-            if e.is_http_network() {
+            if e.is_http_network() || e.is_ws_network() || e.is_ipc_network() {
                 return true;
             }
-            // don't handle ipc because its implementation is uncertain :)
         }
         false
     }
+    /// the node's error object, if it answered with one
+    pub fn json_rpc_error(&self) -> Option<JsonRpcError> {
+        match self {
+            Self::JsonRpcResultError(v) => Some(JsonRpcError::from(v.clone())),
+            _ => None,
+        }
+    }
+    /// minimum wait the node asked for with a 429 or 503
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Busy { retry_after, .. } => *retry_after,
+            Self::Transport(e) => e.retry_after(),
+            _ => None,
+        }
+    }
+    /// the node surely never processed the request, refused connections and 429s only,
+    /// a 503 of a load balancer may come after the request was forwarded
+    pub fn is_rejected(&self) -> bool {
+        match self {
+            Self::Busy { status, .. } => *status == 429,
+            Self::Transport(e) => e.is_rejected(),
+            _ => false,
+        }
+    }
+}
+
+/// transparent for transport and json rpc errors
+impl std::fmt::Display for JRError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailInitialize(msg) => write!(f, "failed to initialize: {}", msg),
+            Self::JRCallSerialize(_) => write!(f, "failed to serialize the json rpc call"),
+            Self::Transport(e) => std::fmt::Display::fmt(e, f),
+            Self::ResponseNotUtf8(_) => write!(f, "response is not utf8"),
+            Self::ResponseNotJson(_) => write!(f, "response is not json"),
+            Self::ResponseNotJsonRpcResponse(_) => write!(f, "response is not a json rpc response"),
+            Self::RateLimited(v) => write!(f, "rate limited: {}", v),
+            Self::Busy { status, .. } => write!(f, "endpoint busy, responded with {}", status),
+            Self::ResponseDoesNotMatchType(_) => {
+                write!(f, "response does not match the expected type")
+            }
+            Self::JsonRpcResultError(v) => {
+                std::fmt::Display::fmt(&JsonRpcError::from(v.clone()), f)
+            }
+            Self::BatchMissingResponses => write!(f, "batch is missing responses"),
+            Self::EmptyResponse => write!(f, "empty response"),
+            Self::NoQuorum(e) => std::fmt::Display::fmt(e, f),
+            Self::CircuitOpen(url) => write!(f, "circuit breaker of {} is open", url),
+            Self::Reverted {
+                reason: RevertReason::Custom,
+                raw,
+            } => write!(f, "execution reverted with custom error {}", raw),
+            Self::Reverted { reason, .. } => write!(f, "execution reverted: {}", reason),
+            Self::Extension(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for JRError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::JRCallSerialize(e)
+            | Self::ResponseNotJson(e)
+            | Self::ResponseNotJsonRpcResponse(e)
+            | Self::ResponseDoesNotMatchType(e) => Some(e),
+            Self::ResponseNotUtf8(e) => Some(e),
+            Self::Transport(e) => std::error::Error::source(e),
+            _ => None,
+        }
+    }
+}
+
+/// `NoQuorum`, open circuits and rate limiting statuses get their own variants
+impl From<RpcTransportErr> for JRError {
+    fn from(e: RpcTransportErr) -> Self {
+        if let RpcTransportErr::Http(http) = &e {
+            if let Some(status) = http.status().filter(|_| e.is_http_rate_limited()) {
+                return Self::Busy {
+                    status: *status,
+                    retry_after: e.retry_after(),
+                };
+            }
+        }
+        match e {
+            RpcTransportErr::NoQuorum(e) => Self::NoQuorum(e),
+            RpcTransportErr::Http(HttpErr::CircuitOpen(url)) => Self::CircuitOpen(url),
+            e => Self::Transport(e),
+        }
+    }
+}
+
+impl From<JsonRpcError> for JRError {
+    fn from(e: JsonRpcError) -> Self {
+        Self::JsonRpcResultError(e.raw)
+    }
 }
 
 impl EthRpc {
@@ -113,6 +254,8 @@ impl EthRpc {
         Ok(Self {
             disable_ratelimit_protection: false,
             batch_chunk_size: Some(20_000),
+            retry_policy: RetryPolicy::default(),
+            layers: Vec::new(),
             transport: RpcTransport::Http(http),
         })
     }
@@ -120,77 +263,51 @@ impl EthRpc {
         Ok(Self {
             disable_ratelimit_protection: false,
             batch_chunk_size: Some(20_000),
+            retry_policy: RetryPolicy::default(),
+            layers: Vec::new(),
             transport: RpcTransport::with_http(http)?,
         })
     }
-    pub fn no_ratelimit_rpc<R>(&self, jr: JRCall) -> Result<R, JRError>
-    where
-        R: for<'a> Deserialize<'a>,
-    {
-        let params = jr.to_vec()?;
-        if self.disable_ratelimit_protection {
-            return self.call_rpc_transport(params.as_slice());
+    /// on top of any transport, e.g. `RpcTransport::custom(my_gateway)`
+    pub fn with_transport(transport: RpcTransport) -> Self {
+        Self {
+            disable_ratelimit_protection: false,
+            batch_chunk_size: Some(20_000),
+            retry_policy: RetryPolicy::default(),
+            layers: Vec::new(),
+            transport,
         }
-        self.inner_no_ratelimit_rpc(params.as_slice())
     }
-    /// no ratelimit and no network errors
-    pub(crate) fn inner_no_ratelimit_rpc<R>(&self, params: &[u8]) -> Result<R, JRError>
-    where
-        R: for<'a> Deserialize<'a>,
-    {
-        let mut backoff_sec = 1;
-        let mut count = 0;
-        loop {
-            count += 1;
-            let res = self.call_rpc_transport(params);
-            if let Err(e) = res {
-                if count > 4 {
-                    error!("Retries fail: {:?}", e);
-                    return Err(e);
-                }
-                if e.is_network_or_ratelimit() {
-                    warn!("Network err or rate limited!");
-                    std::thread::sleep(Duration::from_secs(backoff_sec));
-                    backoff_sec += 1;
-                    continue;
-                }
-                // error!("{:?}", e);
-                return Err(e);
-            }
-
-            return res;
-        }
+    pub fn with_ws(ws: impl ToString) -> Result<Self, JRError> {
+        Ok(Self {
+            disable_ratelimit_protection: false,
+            batch_chunk_size: Some(20_000),
+            retry_policy: RetryPolicy::default(),
+            layers: Vec::new(),
+            transport: RpcTransport::with_ws(ws)?,
+        })
     }
-
-    /// unwraps jsonrpc response into a desired type
-    pub(crate) fn call_rpc_transport<R>(&self, request: &[u8]) -> Result<R, JRError>
+    /// per endpoint latency and error rate for dashboards, see `RpcTransport::endpoint_stats`
+    pub fn endpoint_stats(&self) -> Vec<EndpointStats> {
+        self.transport.endpoint_stats()
+    }
+    /// for health checks, see `RpcTransport::circuit_states`
+    pub fn circuit_states(&self) -> Vec<(SecretUrl, CircuitState)> {
+        self.transport.circuit_states()
+    }
+    pub fn no_ratelimit_rpc<R>(&self, jr: JRCall) -> Result<R, JRError>
     where
         R: for<'a> Deserialize<'a>,
     {
-        let result: Value = self.__call_transport(request)?;
-        let safe_jr_result: SafeJRResult = result.try_into()?;
-        let value = safe_jr_result.result;
-        Ok(serde_json::from_value(value).map_err(|e| JRError::ResponseDoesNotMatchType(e))?)
+        self.call_single(jr)
     }
 
-    /// single rpc call casting ret bytes into any type
-    pub(crate) fn __call_transport<R>(&self, request: &[u8]) -> Result<R, JRError>
+    /// single rpc call casting ret bytes into any type, `methods` of the calls in `request`
+    pub(crate) fn __call_transport<R>(&self, request: &[u8], methods: &[&str]) -> Result<R, JRError>
     where
         R: for<'a> Deserialize<'a>,
     {
-        let res = match self.transport.send(request) {
-            Ok(v) => v,
-            Err(e) => {
-                if let RpcTransportErr::Http(http_err) = &e {
-                    if let HttpErr::FailStatus(_, status) = &http_err {
-                        if status.deref() == &429 {
-                            return Err(JRError::RateLimited(json!(429)));
-                        }
-                    }
-                }
-                return Err(JRError::Transport(e));
-            }
-        };
+        let res = self.transport.send_calls(request, methods)?;
         serde_json::from_slice(res.as_slice()).map_err(|e| JRError::ResponseNotJson(e))
     }
 }
@@ -0,0 +1,459 @@
+use super::{
+    http::{HttpErr, HttpTransport},
+    transport::RpcTransportErr,
+};
+use crate::{JRError, SafeJRResult};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// like `RandomizeHttps` but routes toward the fastest endpoints,
+/// latency and error rate are tracked as exponentially weighted moving averages
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "BalancedConfigRepr")]
+pub struct BalancedConfig {
+    pub endpoints: Vec<HttpTransport>,
+    #[serde(default)]
+    pub selection: Selection,
+    /// weight of the newest sample, above 0 and at most 1
+    #[serde(default = "default_ewma_alpha")]
+    pub ewma_alpha: f64,
+    /// if set and the first endpoint has not answered in time,
+    /// the request is also sent to a second one and the first valid response wins
+    #[serde(default)]
+    pub hedge_after_millis: Option<u64>,
+    /// shared between clones
+    #[serde(skip)]
+    stats: Arc<Mutex<Vec<EndpointStats>>>,
+}
+
+#[derive(Deserialize)]
+struct BalancedConfigRepr {
+    endpoints: Vec<HttpTransport>,
+    #[serde(default)]
+    selection: Selection,
+    #[serde(default = "default_ewma_alpha")]
+    ewma_alpha: f64,
+    #[serde(default)]
+    hedge_after_millis: Option<u64>,
+}
+
+impl TryFrom<BalancedConfigRepr> for BalancedConfig {
+    type Error = String;
+    fn try_from(value: BalancedConfigRepr) -> Result<Self, Self::Error> {
+        let balanced = Self {
+            endpoints: value.endpoints,
+            selection: value.selection,
+            ewma_alpha: value.ewma_alpha,
+            hedge_after_millis: value.hedge_after_millis,
+            stats: Default::default(),
+        };
+        balanced.validate()?;
+        Ok(balanced)
+    }
+}
+
+fn default_ewma_alpha() -> f64 {
+    0.2
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Selection {
+    /// same as `RandomizeHttps`
+    Uniform,
+    /// random, weighted by the inverse of the expected cost
+    Proportional,
+    /// cheaper one of two random endpoints
+    #[default]
+    PowerOfTwo,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EndpointStats {
+    /// redacted
+    pub url: String,
+    /// `None` until the first successful request
+    pub latency_millis: Option<f64>,
+    /// between 0 and 1
+    pub error_rate: f64,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+impl EndpointStats {
+    /// expected time until a successful response, untried endpoints are free so they get measured
+    pub fn cost(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        // never answered, assume it times out
+        let latency = self.latency_millis.unwrap_or(10_000.0);
+        latency.max(1.0) / (1.0 - self.error_rate).max(0.01)
+    }
+
+    fn record(&mut self, alpha: f64, elapsed: Duration, success: bool) {
+        self.requests += 1;
+        let error = if success { 0.0 } else { 1.0 };
+        self.error_rate += alpha * (error - self.error_rate);
+        if !success {
+            self.errors += 1;
+            // a timeout says nothing about how fast the endpoint answers
+            return;
+        }
+        let latency = elapsed.as_secs_f64() * 1000.0;
+        self.latency_millis = Some(match self.latency_millis {
+            Some(avg) => avg + alpha * (latency - avg),
+            None => latency,
+        });
+    }
+}
+
+impl BalancedConfig {
+    pub fn new(endpoints: Vec<HttpTransport>, selection: Selection) -> Self {
+        Self {
+            endpoints,
+            selection,
+            ewma_alpha: default_ewma_alpha(),
+            hedge_after_millis: None,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn with_ewma_alpha(mut self, ewma_alpha: f64) -> Result<Self, String> {
+        self.ewma_alpha = ewma_alpha;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_hedge_after_millis(mut self, hedge_after_millis: u64) -> Self {
+        self.hedge_after_millis = Some(hedge_after_millis);
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // also rejects NaN
+        if !(self.ewma_alpha > 0.0 && self.ewma_alpha <= 1.0) {
+            return Err(format!(
+                "balanced ewma_alpha has to be above 0 and at most 1, got {}",
+                self.ewma_alpha
+            ));
+        }
+        Ok(())
+    }
+
+    /// snapshot, in the order of `endpoints`
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.locked_stats().clone()
+    }
+
+    pub fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send_calls(params, None)
+    }
+
+    /// `methods` of the calls in `params` if known, see `HttpTransport::post_calls`
+    pub(crate) fn send_calls(
+        &self,
+        params: &[u8],
+        methods: Option<&[&str]>,
+    ) -> Result<Vec<u8>, RpcTransportErr> {
+        if self.endpoints.is_empty() {
+            return Err(RpcTransportErr::NoHttps);
+        }
+        let first = self.select(None);
+        let res = match self.hedge_after_millis {
+            Some(delay) if self.endpoints.len() > 1 => {
+                self.send_hedged(params, methods, first, Duration::from_millis(delay))
+            }
+            _ => self.post(first, params, methods),
+        };
+        res.map_err(RpcTransportErr::Http)
+    }
+
+    fn post(
+        &self,
+        idx: usize,
+        params: &[u8],
+        methods: Option<&[&str]>,
+    ) -> Result<Vec<u8>, HttpErr> {
+        let start = Instant::now();
+        let res = self.endpoints[idx].post_calls(params, methods);
+        self.locked_stats()[idx].record(self.ewma_alpha, start.elapsed(), res.is_ok());
+        res
+    }
+
+    /// races a second endpoint if the first one is slow, fails or is throttled,
+    /// every racer runs on its own thread with a clone of its endpoint only
+    fn send_hedged(
+        &self,
+        params: &[u8],
+        methods: Option<&[&str]>,
+        first: usize,
+        delay: Duration,
+    ) -> Result<Vec<u8>, HttpErr> {
+        let (tx, rx) = mpsc::channel();
+        let methods: Option<Vec<String>> =
+            methods.map(|m| m.iter().map(|m| m.to_string()).collect());
+        // endpoints may have changed since the last call, line the stats up before sharing them
+        drop(self.locked_stats());
+        let spawn = |idx: usize| {
+            let (http, stats, alpha) = (
+                self.endpoints[idx].clone(),
+                self.stats.clone(),
+                self.ewma_alpha,
+            );
+            let (params, methods, tx) = (params.to_vec(), methods.clone(), tx.clone());
+            // the loser keeps running in the background and still updates the stats
+            std::thread::spawn(move || {
+                let methods: Option<Vec<&str>> = methods
+                    .as_ref()
+                    .map(|m| m.iter().map(String::as_str).collect());
+                let start = Instant::now();
+                let res = http.post_calls(&params, methods.as_deref());
+                if let Some(s) = stats.lock().unwrap().get_mut(idx) {
+                    s.record(alpha, start.elapsed(), res.is_ok());
+                }
+                let _ = tx.send(res);
+            });
+        };
+        spawn(first);
+        let mut pending = 1;
+        let mut fallback = None;
+        if let Ok(res) = rx.recv_timeout(delay) {
+            if is_valid(&res) {
+                return res;
+            }
+            pending -= 1;
+            fallback = Some(res);
+        }
+        spawn(self.select(Some(first)));
+        pending += 1;
+        drop(tx);
+        for _ in 0..pending {
+            let res = match rx.recv() {
+                Ok(res) => res,
+                Err(_) => break,
+            };
+            if is_valid(&res) {
+                return res;
+            }
+            // a throttled answer is more telling than a failed request
+            if fallback.as_ref().map(|f| f.is_err()).unwrap_or(true) {
+                fallback = Some(res);
+            }
+        }
+        fallback.unwrap_or_else(|| Err(HttpErr::Other("hedged requests panicked".into())))
+    }
+
+    fn locked_stats(&self) -> std::sync::MutexGuard<'_, Vec<EndpointStats>> {
+        let mut stats = self.stats.lock().unwrap();
+        // endpoints are public, keep up if they were changed
+        if stats.len() != self.endpoints.len() {
+            stats.resize(self.endpoints.len(), EndpointStats::default());
+        }
+        for (s, http) in stats.iter_mut().zip(self.endpoints.iter()) {
+            let url = http.url.to_string();
+            if s.url != url {
+                *s = EndpointStats {
+                    url,
+                    ..Default::default()
+                };
+            }
+        }
+        stats
+    }
+
+    /// picks among all endpoints but `exclude`
+    fn select(&self, exclude: Option<usize>) -> usize {
+        let mut rng = rand::thread_rng();
+        let candidates: Vec<usize> = (0..self.endpoints.len())
+            .filter(|i| Some(*i) != exclude)
+            .collect();
+        let len = candidates.len();
+        if len == 1 || self.selection == Selection::Uniform {
+            return candidates[rng.gen_range(0..len)];
+        }
+        let stats = self.locked_stats();
+        let costs: Vec<f64> = candidates.iter().map(|i| stats[*i].cost()).collect();
+        drop(stats);
+        let pos = match self.selection {
+            Selection::PowerOfTwo => {
+                let a = rng.gen_range(0..len);
+                let b = (a + rng.gen_range(1..len)) % len;
+                if costs[b] < costs[a] {
+                    b
+                } else {
+                    a
+                }
+            }
+            _ => match costs.iter().position(|c| *c == 0.0) {
+                Some(unmeasured) => unmeasured,
+                None => {
+                    let weights: Vec<f64> = costs.iter().map(|c| 1.0 / c).collect();
+                    let mut pick = rng.gen_range(0.0..weights.iter().sum::<f64>());
+                    weights
+                        .iter()
+                        .position(|w| {
+                            if pick < *w {
+                                return true;
+                            }
+                            pick -= w;
+                            false
+                        })
+                        .unwrap_or(len - 1)
+                }
+            },
+        };
+        candidates[pos]
+    }
+}
+
+/// throttled answers and garbage lose the race, other json rpc errors (e.g. reverts)
+/// would come back the same from any endpoint. batches can't be judged as a whole
+fn is_valid(res: &Result<Vec<u8>, HttpErr>) -> bool {
+    let bytes = match res {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(Value::Array(_)) => true,
+        Ok(v) => matches!(
+            SafeJRResult::try_from(v),
+            Ok(_) | Err(JRError::JsonRpcResultError(_))
+        ),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        EthRpc, RetryPolicy, RpcTransport,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    fn delayed(millis: u64, status: u16) -> HttpTransport {
+        let url = serve(move |req| {
+            std::thread::sleep(Duration::from_millis(millis));
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            MockResponse::status(
+                status,
+                json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"}),
+            )
+        });
+        HttpTransport::new(&url).unwrap()
+    }
+
+    fn answering(error: Value, calls: Arc<AtomicUsize>) -> HttpTransport {
+        let url = serve(move |req| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": call["id"], "error": error}))
+        });
+        HttpTransport::new(&url).unwrap()
+    }
+
+    #[test]
+    fn test_hedged() {
+        let throttled = json!({"code": 429, "message": "Your app has exceeded its compute units per second capacity"});
+        let cases = [
+            // too slow, the fast one takes over
+            vec![delayed(2_000, 200), delayed(0, 200)],
+            // failing fast, no need to wait for the delay
+            vec![delayed(0, 502), delayed(0, 200)],
+            vec![answering(throttled, Default::default()), delayed(0, 200)],
+        ];
+        for endpoints in cases {
+            let balanced =
+                BalancedConfig::new(endpoints, Selection::Uniform).with_hedge_after_millis(50);
+            let client = EthRpc::with_transport(RpcTransport::Balanced(balanced))
+                .with_retry_policy(RetryPolicy::none());
+            let start = Instant::now();
+            for _ in 0..5 {
+                assert_eq!(client.get_block_number().unwrap(), 1);
+            }
+            assert!(start.elapsed() < Duration::from_millis(1_000));
+        }
+    }
+
+    #[test]
+    fn test_revert_not_hedged() {
+        let calls: Arc<AtomicUsize> = Default::default();
+        let revert = json!({"code": 3, "message": "execution reverted", "data": "0x"});
+        let endpoints = vec![
+            answering(revert.clone(), calls.clone()),
+            answering(revert, calls.clone()),
+        ];
+        let balanced =
+            BalancedConfig::new(endpoints, Selection::Uniform).with_hedge_after_millis(50);
+        let client = EthRpc::with_transport(RpcTransport::Balanced(balanced))
+            .with_retry_policy(RetryPolicy::none());
+        for _ in 0..5 {
+            assert!(client.get_block_number().is_err());
+        }
+        // one endpoint per call, the revert is an answer
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+        let stats = client.endpoint_stats();
+        assert_eq!(stats.iter().map(|s| s.errors).sum::<u64>(), 0);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: RpcTransport = serde_json::from_value(json!({
+            "Balanced": {
+                "endpoints": ["https://eth-mainnet.g.alchemy.com/v2/fake1", "https://rpc.ankr.com/eth"],
+                "selection": "Proportional",
+                "hedge_after_millis": 300
+            }
+        }))
+        .unwrap();
+        if let RpcTransport::Balanced(b) = config {
+            assert_eq!(b.selection, Selection::Proportional);
+            assert_eq!(b.ewma_alpha, 0.2);
+            assert_eq!(b.hedge_after_millis, Some(300));
+        } else {
+            panic!("not balanced")
+        }
+        for alpha in [0.0, -0.5, 1.5] {
+            let res = serde_json::from_value::<RpcTransport>(json!({
+                "Balanced": { "endpoints": ["https://rpc.ankr.com/eth"], "ewma_alpha": alpha }
+            }));
+            assert!(res.is_err(), "{}", alpha);
+        }
+        let balanced = BalancedConfig::new(Vec::new(), Selection::Uniform);
+        assert!(balanced.clone().with_ewma_alpha(f64::NAN).is_err());
+        assert_eq!(balanced.with_ewma_alpha(1.0).unwrap().ewma_alpha, 1.0);
+    }
+
+    #[test]
+    fn test_routes_to_fastest() {
+        for selection in [Selection::PowerOfTwo, Selection::Proportional] {
+            let balanced = BalancedConfig::new(
+                vec![delayed(30, 200), delayed(0, 200), delayed(0, 500)],
+                selection,
+            );
+            let client = EthRpc::with_transport(RpcTransport::Balanced(balanced))
+                .with_retry_policy(RetryPolicy::none());
+            for _ in 0..100 {
+                let _ = client.get_block_number();
+            }
+            let stats = client.endpoint_stats();
+            assert_eq!(stats.len(), 3);
+            assert!(stats[0].latency_millis.unwrap() >= 30.0);
+            assert!(stats[2].error_rate > 0.0);
+            assert_eq!(stats[2].latency_millis, None);
+            // the fast healthy endpoint gets most of the traffic,
+            // power of two picks it whenever it is one of the two (2/3)
+            assert!(stats[1].requests > 50, "{:?} {:?}", selection, stats);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use super::SecretUrl;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// stops sending to an http endpoint that keeps failing hard (dns outage, revoked key, 5xx),
+/// requests fail fast with `HttpErr::CircuitOpen` until a probe gets through again.
+/// 429 and 503 don't count, backing off from those is the job of the `RetryPolicy`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CircuitBreaker {
+    /// consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    /// how long an open circuit fails fast before letting a single probe through
+    pub probe_interval_millis: u64,
+    /// consecutive successful probes that close the circuit again
+    pub success_threshold: u32,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            probe_interval_millis: 30_000,
+            success_threshold: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CircuitState {
+    Closed,
+    /// failing fast
+    Open,
+    /// a probe is let through, its result decides
+    HalfOpen,
+}
+
+struct Circuit {
+    state: CircuitState,
+    failures: u32,
+    successes: u32,
+    opened_at: Instant,
+    probing: bool,
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            failures: 0,
+            successes: 0,
+            opened_at: Instant::now(),
+            probing: false,
+        }
+    }
+}
+
+/// lives in the `HttpTransport` so that its clones share it,
+/// not part of the config, never makes two transports unequal
+#[derive(Clone, Default)]
+pub(crate) struct SharedCircuit(Arc<Mutex<Circuit>>);
+
+impl PartialEq for SharedCircuit {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SharedCircuit {}
+
+impl CircuitBreaker {
+    pub(crate) fn state(&self, circuit: &SharedCircuit) -> CircuitState {
+        let circuit = circuit.0.lock().unwrap();
+        // reported as half open once the probe is due, even if nobody sent it yet
+        if circuit.state == CircuitState::Open && self.probe_due(&circuit) {
+            return CircuitState::HalfOpen;
+        }
+        circuit.state
+    }
+
+    /// `false` if the request has to fail fast
+    pub(crate) fn allow(&self, circuit: &SharedCircuit) -> bool {
+        let mut circuit = circuit.0.lock().unwrap();
+        match circuit.state {
+            CircuitState::Closed => true,
+            CircuitState::Open if self.probe_due(&circuit) => {
+                circuit.state = CircuitState::HalfOpen;
+                circuit.probing = true;
+                true
+            }
+            CircuitState::Open => false,
+            // one probe at a time
+            CircuitState::HalfOpen if !circuit.probing => {
+                circuit.probing = true;
+                true
+            }
+            CircuitState::HalfOpen => false,
+        }
+    }
+
+    pub(crate) fn record(&self, circuit: &SharedCircuit, endpoint: &SecretUrl, success: bool) {
+        let mut circuit = circuit.0.lock().unwrap();
+        let circuit = &mut *circuit;
+        match (circuit.state, success) {
+            (CircuitState::Closed, true) => circuit.failures = 0,
+            (CircuitState::Closed, false) => {
+                circuit.failures += 1;
+                if circuit.failures >= self.failure_threshold {
+                    warn!("Circuit of {} opened", endpoint);
+                    Self::open(circuit);
+                }
+            }
+            (CircuitState::HalfOpen, true) => {
+                circuit.probing = false;
+                circuit.successes += 1;
+                if circuit.successes >= self.success_threshold {
+                    *circuit = Circuit::default();
+                }
+            }
+            (CircuitState::HalfOpen, false) => Self::open(circuit),
+            // a request sent before the circuit opened
+            (CircuitState::Open, _) => {}
+        }
+    }
+
+    /// a busy answer tells nothing about the endpoint's health, a probe stays half open
+    /// and the next request probes again
+    pub(crate) fn skip(&self, circuit: &SharedCircuit) {
+        circuit.0.lock().unwrap().probing = false;
+    }
+
+    fn open(circuit: &mut Circuit) {
+        circuit.state = CircuitState::Open;
+        circuit.opened_at = Instant::now();
+        circuit.probing = false;
+        circuit.successes = 0;
+    }
+
+    fn probe_due(&self, circuit: &Circuit) -> bool {
+        circuit.opened_at.elapsed() >= Duration::from_millis(self.probe_interval_millis)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        EthRpc, HttpErr, HttpTransport, JRError, RpcTransport,
+    };
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_circuit_breaker() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let inner = hits.clone();
+        let healthy = Arc::new(AtomicUsize::new(0));
+        let inner_healthy = healthy.clone();
+        let url = serve(move |req| {
+            inner.fetch_add(1, Ordering::SeqCst);
+            if inner_healthy.load(Ordering::SeqCst) == 0 {
+                return MockResponse::status(401, "invalid api key");
+            }
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"}))
+        });
+        let breaker = CircuitBreaker {
+            failure_threshold: 3,
+            probe_interval_millis: 200,
+            ..Default::default()
+        };
+        let http = HttpTransport::new(&url)
+            .unwrap()
+            .with_circuit_breaker(breaker);
+        let client = EthRpc::with_transport(RpcTransport::Http(http));
+        for _ in 0..3 {
+            assert!(matches!(
+                client.get_block_number().unwrap_err(),
+                JRError::Transport(_)
+            ));
+        }
+        assert_eq!(client.circuit_states()[0].1, CircuitState::Open);
+        // fails fast without reaching the node or retrying
+        for _ in 0..10 {
+            assert!(matches!(
+                client.get_block_number().unwrap_err(),
+                JRError::CircuitOpen(_)
+            ));
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // failed probe opens it again
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(client.circuit_states()[0].1, CircuitState::HalfOpen);
+        assert!(matches!(
+            client.get_block_number().unwrap_err(),
+            JRError::Transport(_)
+        ));
+        assert_eq!(client.circuit_states()[0].1, CircuitState::Open);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+
+        healthy.store(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(client.get_block_number().unwrap(), 1);
+        assert_eq!(client.circuit_states()[0].1, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_busy_is_not_a_failure() {
+        let status = Arc::new(AtomicUsize::new(429));
+        let inner = status.clone();
+        let url = serve(move |_| MockResponse::status(inner.load(Ordering::SeqCst) as u16, "{}"));
+        let breaker = CircuitBreaker {
+            failure_threshold: 1,
+            probe_interval_millis: 100,
+            ..Default::default()
+        };
+        let http = HttpTransport::new(&url)
+            .unwrap()
+            .with_circuit_breaker(breaker);
+        for _ in 0..3 {
+            assert!(http.post(b"{}").is_err());
+        }
+        assert_eq!(http.circuit_state(), Some(CircuitState::Closed));
+
+        status.store(500, Ordering::SeqCst);
+        assert!(http.post(b"{}").is_err());
+        assert_eq!(http.circuit_state(), Some(CircuitState::Open));
+        // a busy probe neither closes nor reopens, the next request probes again
+        std::thread::sleep(Duration::from_millis(150));
+        status.store(503, Ordering::SeqCst);
+        assert!(matches!(http.post(b"{}"), Err(HttpErr::Busy { .. })));
+        assert_eq!(http.circuit_state(), Some(CircuitState::HalfOpen));
+        assert!(matches!(http.post(b"{}"), Err(HttpErr::Busy { .. })));
+        status.store(200, Ordering::SeqCst);
+        assert!(http.post(b"{}").is_ok());
+        assert_eq!(http.circuit_state(), Some(CircuitState::Closed));
+    }
+
+    #[test]
+    fn test_state_per_transport() {
+        let url = serve(|_| MockResponse::status(500, "down"));
+        let breaker = CircuitBreaker {
+            failure_threshold: 1,
+            ..Default::default()
+        };
+        let http = HttpTransport::new(&url)
+            .unwrap()
+            .with_circuit_breaker(breaker.clone());
+        let clone = http.clone();
+        assert!(http.post(b"{}").is_err());
+        assert_eq!(clone.circuit_state(), Some(CircuitState::Open));
+        // same url and config, but not a clone
+        let other = HttpTransport::new(&url)
+            .unwrap()
+            .with_circuit_breaker(breaker);
+        assert_eq!(other.circuit_state(), Some(CircuitState::Closed));
+        assert_eq!(other, http);
+    }
+}
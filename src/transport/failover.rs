@@ -0,0 +1,373 @@
+use super::{
+    http::{HttpErr, HttpTransport},
+    transport::RpcTransportErr,
+};
+use crate::rpc_impl::is_idempotent_method;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// tries endpoints in priority (or weighted) order within a single `send`,
+/// endpoints failing `failure_threshold` times in a row are skipped for `cooldown_millis`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "FailoverConfigRepr")]
+pub struct FailoverConfig {
+    /// first one is the primary
+    pub endpoints: Vec<HttpTransport>,
+    /// if not empty, endpoints are tried in weighted random order instead of priority order
+    #[serde(default)]
+    pub weights: Vec<u32>,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_cooldown_millis")]
+    pub cooldown_millis: u64,
+    /// shared between clones
+    #[serde(skip)]
+    health: Arc<Mutex<Vec<EndpointHealth>>>,
+}
+
+#[derive(Deserialize)]
+struct FailoverConfigRepr {
+    endpoints: Vec<HttpTransport>,
+    #[serde(default)]
+    weights: Vec<u32>,
+    #[serde(default = "default_failure_threshold")]
+    failure_threshold: u32,
+    #[serde(default = "default_cooldown_millis")]
+    cooldown_millis: u64,
+}
+
+impl TryFrom<FailoverConfigRepr> for FailoverConfig {
+    type Error = String;
+    fn try_from(value: FailoverConfigRepr) -> Result<Self, Self::Error> {
+        let failover = Self {
+            endpoints: value.endpoints,
+            weights: value.weights,
+            failure_threshold: value.failure_threshold,
+            cooldown_millis: value.cooldown_millis,
+            health: Default::default(),
+        };
+        failover.validate()?;
+        Ok(failover)
+    }
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_cooldown_millis() -> u64 {
+    30_000
+}
+
+#[derive(Debug, Clone, Default)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until.map(|t| t <= now).unwrap_or(true)
+    }
+}
+
+impl FailoverConfig {
+    /// at least one endpoint
+    pub fn new(endpoints: Vec<HttpTransport>) -> Result<Self, String> {
+        let failover = Self {
+            endpoints,
+            weights: Vec::new(),
+            failure_threshold: default_failure_threshold(),
+            cooldown_millis: default_cooldown_millis(),
+            health: Default::default(),
+        };
+        failover.validate()?;
+        Ok(failover)
+    }
+
+    /// one weight per endpoint
+    pub fn with_weights(mut self, weights: Vec<u32>) -> Result<Self, String> {
+        self.weights = weights;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// at least 1
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Result<Self, String> {
+        self.failure_threshold = failure_threshold;
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_cooldown_millis(mut self, cooldown_millis: u64) -> Self {
+        self.cooldown_millis = cooldown_millis;
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.endpoints.is_empty() {
+            return Err("failover needs at least one endpoint".into());
+        }
+        if !self.weights.is_empty() && self.weights.len() != self.endpoints.len() {
+            return Err(format!(
+                "failover has {} weights for {} endpoints",
+                self.weights.len(),
+                self.endpoints.len()
+            ));
+        }
+        if self.failure_threshold == 0 {
+            return Err("failover failure_threshold has to be positive".into());
+        }
+        Ok(())
+    }
+
+    /// `Some(false)` while the endpoint at `idx` is cooling down, `None` if there is none
+    pub fn is_healthy(&self, idx: usize) -> Option<bool> {
+        let health = self.health();
+        health.get(idx).map(|h| h.is_healthy(Instant::now()))
+    }
+
+    pub fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send_calls(params, None)
+    }
+
+    /// `methods` of the calls in `params` if known, see `HttpTransport::post_calls`
+    pub(crate) fn send_calls(
+        &self,
+        params: &[u8],
+        methods: Option<&[&str]>,
+    ) -> Result<Vec<u8>, RpcTransportErr> {
+        let idempotent = match methods {
+            Some(methods) => methods.iter().all(|m| is_idempotent_method(m)),
+            None => is_idempotent(params),
+        };
+        let mut last_err = RpcTransportErr::NoHttps;
+        for idx in self.attempt_order() {
+            match self.endpoints[idx].post_calls(params, methods) {
+                Ok(v) => {
+                    self.record(idx, true);
+                    return Ok(v);
+                }
+                Err(e) => {
+                    warn!("Failover endpoint {} failed: {:?}", idx, e);
+                    self.record(idx, false);
+                    // a transaction might land after a timeout, only move on if it surely wasn't sent
+                    let unsent = matches!(e, HttpErr::CircuitOpen(_));
+                    let e = RpcTransportErr::Http(e);
+                    if !idempotent && !unsent && !e.is_rejected() {
+                        return Err(e);
+                    }
+                    last_err = e;
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    fn health(&self) -> std::sync::MutexGuard<'_, Vec<EndpointHealth>> {
+        let mut health = self.health.lock().unwrap();
+        // endpoints are public, keep up if they were changed
+        health.resize(self.endpoints.len(), EndpointHealth::default());
+        health
+    }
+
+    /// healthy endpoints first, cooling down ones only as a last resort
+    fn attempt_order(&self) -> Vec<usize> {
+        let order = if self.weights.is_empty() {
+            (0..self.endpoints.len()).collect()
+        } else {
+            self.weighted_order()
+        };
+        let now = Instant::now();
+        let health = self.health();
+        let (mut healthy, cooling): (Vec<usize>, Vec<usize>) =
+            order.into_iter().partition(|i| health[*i].is_healthy(now));
+        healthy.extend(cooling);
+        healthy
+    }
+
+    /// weighted shuffle, missing weights count as 0 and go last
+    fn weighted_order(&self) -> Vec<usize> {
+        let mut rng = rand::thread_rng();
+        let mut pool: Vec<(usize, u64)> = (0..self.endpoints.len())
+            .map(|i| (i, self.weights.get(i).copied().unwrap_or(0) as u64))
+            .collect();
+        let mut order = Vec::with_capacity(pool.len());
+        while !pool.is_empty() {
+            let total: u64 = pool.iter().map(|(_, w)| w).sum();
+            if total == 0 {
+                order.extend(pool.iter().map(|(i, _)| *i));
+                break;
+            }
+            let mut pick = rng.gen_range(0..total);
+            let pos = pool
+                .iter()
+                .position(|(_, w)| {
+                    if pick < *w {
+                        return true;
+                    }
+                    pick -= w;
+                    false
+                })
+                .unwrap();
+            order.push(pool.remove(pos).0);
+        }
+        order
+    }
+
+    fn record(&self, idx: usize, success: bool) {
+        let mut health = self.health();
+        let h = &mut health[idx];
+        if success {
+            *h = EndpointHealth::default();
+            return;
+        }
+        h.consecutive_failures += 1;
+        if h.consecutive_failures >= self.failure_threshold {
+            h.unhealthy_until = Some(Instant::now() + Duration::from_millis(self.cooldown_millis));
+        }
+    }
+}
+
+/// of a raw single or batch request, garbage can't submit anything
+fn is_idempotent(params: &[u8]) -> bool {
+    let call_is_idempotent = |call: &Value| {
+        call["method"]
+            .as_str()
+            .map(is_idempotent_method)
+            .unwrap_or(true)
+    };
+    match serde_json::from_slice::<Value>(params) {
+        Ok(Value::Array(calls)) => calls.iter().all(call_is_idempotent),
+        Ok(call) => call_is_idempotent(&call),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        EthRpc, HttpErr, RetryPolicy, RpcTransport,
+    };
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counting(status: u16) -> (HttpTransport, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let inner = hits.clone();
+        let url = serve(move |req| {
+            inner.fetch_add(1, Ordering::SeqCst);
+            let call: Value = serde_json::from_slice(&req.body).unwrap();
+            MockResponse::status(
+                status,
+                json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"}),
+            )
+        });
+        (HttpTransport::new(&url).unwrap(), hits)
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: RpcTransport = serde_json::from_value(json!({
+            "Failover": {
+                "endpoints": ["https://eth-mainnet.g.alchemy.com/v2/fake1", "https://rpc.ankr.com/eth"],
+                "cooldown_millis": 1000
+            }
+        }))
+        .unwrap();
+        if let RpcTransport::Failover(f) = config {
+            assert_eq!(f.endpoints.len(), 2);
+            assert_eq!(f.failure_threshold, 3);
+        } else {
+            panic!("not failover")
+        }
+
+        let invalid = [
+            json!({"endpoints": []}),
+            json!({"endpoints": ["https://rpc.ankr.com/eth"], "weights": [1, 2]}),
+            json!({"endpoints": ["https://rpc.ankr.com/eth"], "failure_threshold": 0}),
+        ];
+        for config in invalid {
+            assert!(serde_json::from_value::<FailoverConfig>(config).is_err());
+        }
+        assert!(FailoverConfig::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_failover_skips_unhealthy() {
+        let (bad, bad_hits) = counting(502);
+        let (good, good_hits) = counting(200);
+        let failover = FailoverConfig::new(vec![bad, good])
+            .unwrap()
+            .with_failure_threshold(2)
+            .unwrap();
+        let client = EthRpc::with_transport(RpcTransport::Failover(failover.clone()))
+            .with_retry_policy(RetryPolicy::none());
+        for _ in 0..5 {
+            assert_eq!(client.get_block_number().unwrap(), 1);
+        }
+        // primary was retried until it hit the threshold, then skipped
+        assert_eq!(bad_hits.load(Ordering::SeqCst), 2);
+        assert_eq!(good_hits.load(Ordering::SeqCst), 5);
+        assert_eq!(failover.is_healthy(0), Some(false));
+        assert_eq!(failover.is_healthy(1), Some(true));
+        assert_eq!(failover.is_healthy(2), None);
+    }
+
+    #[test]
+    fn test_failover_all_down() {
+        let (bad, _) = counting(503);
+        let failover = FailoverConfig::new(vec![bad.clone(), bad])
+            .unwrap()
+            .with_failure_threshold(1)
+            .unwrap();
+        // cooling endpoints are still tried as a last resort
+        for _ in 0..2 {
+            let err = failover.send(b"{}").unwrap_err();
+            assert!(matches!(err, RpcTransportErr::Http(HttpErr::Busy { .. })));
+        }
+    }
+
+    #[test]
+    fn test_weighted_order() {
+        let (a, _) = counting(200);
+        let failover = FailoverConfig::new(vec![a.clone(), a.clone(), a])
+            .unwrap()
+            .with_weights(vec![0, 1, 0])
+            .unwrap();
+        for _ in 0..10 {
+            assert_eq!(failover.attempt_order()[0], 1);
+        }
+    }
+
+    #[test]
+    fn test_tx_not_resent_after_timeout() {
+        let tx = br#"{"jsonrpc":"2.0","id":0,"method":"eth_sendRawTransaction","params":["0x00"]}"#;
+        let (good, good_hits) = counting(200);
+        let url = serve(|_| {
+            std::thread::sleep(Duration::from_millis(500));
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": 0, "result": "0x1"}))
+        });
+        let slow = HttpTransport::new(&url).unwrap().with_timeouts(1_000, 100);
+        let failover = FailoverConfig::new(vec![slow, good.clone()]).unwrap();
+        assert!(failover.send(tx).is_err());
+        assert_eq!(good_hits.load(Ordering::SeqCst), 0);
+
+        // refused never reached the node
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+        let refused = HttpTransport::new(&url).unwrap();
+        let failover = FailoverConfig::new(vec![refused, good]).unwrap();
+        assert!(failover.send(tx).is_ok());
+        assert_eq!(good_hits.load(Ordering::SeqCst), 1);
+    }
+}
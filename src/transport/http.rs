@@ -1,111 +1,619 @@
-use oxhttp::{
-    model::{InvalidHeader, Method, Request, Status, Url},
-    Client,
+use super::{
+    circuit_breaker::{CircuitBreaker, CircuitState, SharedCircuit},
+    jwt::JwtSecret,
+    rate_limit::RateLimit,
+    secret_url::SecretUrl,
 };
-use serde::{de::Error, Deserialize, Deserializer, Serialize};
-use std::{borrow::Cow, time::Duration};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use oxhttp::model::{HeaderName, HeaderValue, InvalidHeader, Status, Url};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    ops::Deref,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use ureq::{Agent, AgentBuilder, Request};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct HttpTransport(#[serde(deserialize_with = "validate_url")] pub String);
+/// idle keep-alive connections kept per host, roughly the number of threads hammering one node
+const MAX_IDLE_PER_HOST: usize = 64;
 
-impl TryFrom<String> for HttpTransport {
+/// serialized as a plain url string unless some option differs from the defaults,
+/// `Debug` hides keys in the url, header values and credentials
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "HttpTransportRepr", into = "HttpTransportRepr")]
+pub struct HttpTransport {
+    pub url: SecretUrl,
+    pub connect_timeout_millis: u64,
+    /// per read from the socket, not for the whole response
+    pub read_timeout_millis: u64,
+    /// sent with every request, e.g. api keys
+    pub headers: BTreeMap<String, String>,
+    pub auth: Option<HttpAuth>,
+    /// throttles requests before they are sent, see `RateLimit`
+    pub rate_limit: Option<RateLimit>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// state of the `circuit_breaker`, shared between clones
+    circuit: SharedCircuit,
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HttpAuth {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer(String),
+    /// engine api, a fresh token is minted for every request
+    Jwt(JwtSecret),
+}
+
+impl HttpAuth {
+    fn header_value(&self) -> String {
+        match self {
+            Self::Basic { username, password } => {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", username, password))
+                )
+            }
+            Self::Bearer(token) => format!("Bearer {}", token),
+            Self::Jwt(secret) => format!("Bearer {}", secret.token()),
+        }
+    }
+}
+
+impl std::fmt::Debug for HttpAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            Self::Bearer(_) => f.debug_tuple("Bearer").field(&"***").finish(),
+            Self::Jwt(secret) => f.debug_tuple("Jwt").field(secret).finish(),
+        }
+    }
+}
+
+impl std::fmt::Debug for HttpTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let headers: BTreeMap<&String, &str> = self.headers.keys().map(|k| (k, "***")).collect();
+        f.debug_struct("HttpTransport")
+            .field("url", &self.url)
+            .field("connect_timeout_millis", &self.connect_timeout_millis)
+            .field("read_timeout_millis", &self.read_timeout_millis)
+            .field("headers", &headers)
+            .field("auth", &self.auth)
+            .field("rate_limit", &self.rate_limit)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .finish()
+    }
+}
+
+fn default_timeout_millis() -> u64 {
+    10_000
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum HttpTransportRepr {
+    Url(SecretUrl),
+    Config {
+        url: SecretUrl,
+        #[serde(default = "default_timeout_millis")]
+        connect_timeout_millis: u64,
+        #[serde(default = "default_timeout_millis")]
+        read_timeout_millis: u64,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth: Option<HttpAuth>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rate_limit: Option<RateLimit>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_breaker: Option<CircuitBreaker>,
+    },
+}
+
+impl TryFrom<HttpTransportRepr> for HttpTransport {
     type Error = String;
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        Url::parse(value.as_str()).map_err(|e| format!("{:?}", e))?;
-        Ok(HttpTransport(value))
+    fn try_from(value: HttpTransportRepr) -> Result<Self, Self::Error> {
+        let http = match value {
+            HttpTransportRepr::Url(url) => Self::with_url(url),
+            HttpTransportRepr::Config {
+                url,
+                connect_timeout_millis,
+                read_timeout_millis,
+                headers,
+                auth,
+                rate_limit,
+                circuit_breaker,
+            } => Self {
+                url,
+                connect_timeout_millis,
+                read_timeout_millis,
+                headers,
+                auth,
+                rate_limit,
+                circuit_breaker,
+                circuit: SharedCircuit::default(),
+            },
+        };
+        http.validate()?;
+        Ok(http)
+    }
+}
+
+impl From<HttpTransport> for HttpTransportRepr {
+    fn from(value: HttpTransport) -> Self {
+        if value == HttpTransport::with_url(value.url.clone()) {
+            return Self::Url(value.url);
+        }
+        Self::Config {
+            url: value.url,
+            connect_timeout_millis: value.connect_timeout_millis,
+            read_timeout_millis: value.read_timeout_millis,
+            headers: value.headers,
+            auth: value.auth,
+            rate_limit: value.rate_limit,
+            circuit_breaker: value.circuit_breaker,
+        }
     }
 }
 
-fn validate_url<'de, D>(deserializer: D) -> Result<String, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = Cow::<str>::deserialize(deserializer)?;
-    // check if url parses
-    Url::parse(&s).map_err(D::Error::custom)?;
-    Ok(s.into())
+/// same as `HttpTransport::new`
+impl TryFrom<String> for HttpTransport {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
 }
 
 #[derive(Debug)]
 pub enum HttpErr {
+    /// never returned, headers are validated when the transport is built, kept for compatibility
     FailedToAddHeader(InvalidHeader),
     IO(std::io::Error),
     BodyIO(std::io::Error),
+    /// redacted url and status
     FailStatus(String, Status),
+    /// 429 too many requests or 503 service unavailable,
+    /// with `Retry-After` if the server sent one in seconds
+    Busy {
+        url: SecretUrl,
+        status: Status,
+        retry_after: Option<Duration>,
+    },
+    /// failed fast without sending, see `CircuitBreaker`
+    CircuitOpen(SecretUrl),
     Other(String),
 }
 
+impl HttpErr {
+    /// 429 too many requests or 503 service unavailable
+    pub fn is_busy(&self) -> bool {
+        matches!(self, Self::Busy { .. })
+    }
+    /// of a failed response
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Self::FailStatus(_, status) | Self::Busy { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+    /// stable name for grouping in logs and alerts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::FailedToAddHeader(_) => "http_invalid_header",
+            Self::IO(_) => "http_io",
+            Self::BodyIO(_) => "http_body_io",
+            Self::FailStatus(..) => "http_status",
+            Self::Busy { .. } => "http_busy",
+            Self::CircuitOpen(_) => "circuit_open",
+            Self::Other(_) => "http_other",
+        }
+    }
+}
+
+impl std::fmt::Display for HttpErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailedToAddHeader(_) => write!(f, "invalid http header"),
+            Self::IO(_) => write!(f, "http request failed"),
+            Self::BodyIO(_) => write!(f, "failed to read the http response body"),
+            Self::FailStatus(url, status) => write!(f, "{} responded with {}", url, status),
+            Self::Busy { url, status, .. } => write!(f, "{} responded with {}", url, status),
+            Self::CircuitOpen(url) => write!(f, "circuit breaker of {} is open", url),
+            Self::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for HttpErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::FailedToAddHeader(e) => Some(e),
+            Self::IO(e) | Self::BodyIO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// process wide and keyed by timeouts, so that every `HttpTransport` and its clones reuse
+/// keep-alive connections instead of paying tcp and tls handshakes per request
+fn agent(connect_timeout_millis: u64, read_timeout_millis: u64) -> Agent {
+    static AGENTS: OnceLock<Mutex<HashMap<(u64, u64), Agent>>> = OnceLock::new();
+    let mut agents = AGENTS.get_or_init(Default::default).lock().unwrap();
+    agents
+        .entry((connect_timeout_millis, read_timeout_millis))
+        .or_insert_with(|| {
+            AgentBuilder::new()
+                .timeout_connect(Duration::from_millis(connect_timeout_millis))
+                .timeout_read(Duration::from_millis(read_timeout_millis))
+                .max_idle_connections(MAX_IDLE_PER_HOST * 16)
+                .max_idle_connections_per_host(MAX_IDLE_PER_HOST)
+                .build()
+        })
+        .clone()
+}
+
 impl HttpTransport {
     pub fn post(&self, params: &[u8]) -> Result<Vec<u8>, HttpErr> {
-        let mut client = Client::new();
-        client.set_global_timeout(Duration::from_secs(10));
-        // have to copy here
-        let params = params.to_vec();
-        let req = Request::builder(
-            Method::POST,
-            self.0
-                // safe to unwrap because serde deserialize checks parsing
-                .parse()
-                .unwrap(),
-        )
-        .with_header("content-type", "application/json")
-        .map_err(|e| HttpErr::FailedToAddHeader(e))?
-        .with_body(params);
-        let res = client.request(req).map_err(|e| HttpErr::IO(e))?;
-
-        let status = res.status();
-        if !status.is_successful() {
-            return Err(HttpErr::FailStatus(self.0.clone(), status));
-        }
-        let res = res.into_body().to_vec().map_err(|e| HttpErr::BodyIO(e))?;
-
-        Ok(res)
+        self.post_calls(params, None)
+    }
+
+    /// `methods` of the calls in `params` if known, the rate limit parses the body otherwise
+    pub(crate) fn post_calls(
+        &self,
+        params: &[u8],
+        methods: Option<&[&str]>,
+    ) -> Result<Vec<u8>, HttpErr> {
+        if let Some(breaker) = &self.circuit_breaker {
+            if !breaker.allow(&self.circuit) {
+                return Err(HttpErr::CircuitOpen(self.url.clone()));
+            }
+        }
+        if let Some(limit) = &self.rate_limit {
+            let cost = match methods {
+                Some(methods) => limit.calls_cost(methods),
+                None => limit.request_cost(params),
+            };
+            limit.acquire(self.url.expose(), cost);
+        }
+        let res = self
+            .request("POST")
+            .set("content-type", "application/json")
+            .send_bytes(params);
+        let res = self.read_response(res);
+        if let Some(breaker) = &self.circuit_breaker {
+            match &res {
+                Err(e) if e.is_busy() => breaker.skip(&self.circuit),
+                res => breaker.record(&self.circuit, &self.url, res.is_ok()),
+            }
+        }
+        res
+    }
+
+    /// `None` without a `circuit_breaker`
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker
+            .as_ref()
+            .map(|b| b.state(&self.circuit))
     }
 
     pub fn get(&self) -> Result<Vec<u8>, HttpErr> {
-        let mut client = Client::new();
-        client.set_global_timeout(Duration::from_secs(10));
-        let req = Request::builder(
-            Method::GET,
-            self.0
-                // safe to unwrap because serde deserialize checks parsing
-                .parse()
-                .unwrap(),
-        )
-        .build();
-        let res = client.request(req).map_err(|e| HttpErr::IO(e))?;
+        let res = self.request("GET").call();
+        self.read_response(res)
+    }
 
-        let status = res.status();
-        if !status.is_successful() {
-            return Err(HttpErr::FailStatus(self.0.clone(), status));
+    pub fn with_timeouts(mut self, connect_timeout_millis: u64, read_timeout_millis: u64) -> Self {
+        self.connect_timeout_millis = connect_timeout_millis;
+        self.read_timeout_millis = read_timeout_millis;
+        self
+    }
+
+    pub fn with_header(
+        mut self,
+        name: impl ToString,
+        value: impl ToString,
+    ) -> Result<Self, String> {
+        self.headers.insert(name.to_string(), value.to_string());
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_auth(mut self, auth: HttpAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Result<Self, String> {
+        self.rate_limit = Some(rate_limit);
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    fn request(&self, method: &str) -> Request {
+        let mut req = agent(self.connect_timeout_millis, self.read_timeout_millis)
+            .request(method, self.url.expose());
+        for (name, value) in self.headers.iter() {
+            req = req.set(name, value);
         }
-        let res = res.into_body().to_vec().map_err(|e| HttpErr::BodyIO(e))?;
+        if let Some(auth) = &self.auth {
+            req = req.set("authorization", &auth.header_value());
+        }
+        req
+    }
+
+    /// the body has to be read to the end for the connection to go back into the pool
+    fn read_response(&self, res: Result<ureq::Response, ureq::Error>) -> Result<Vec<u8>, HttpErr> {
+        let res = match res {
+            Ok(res) => res,
+            Err(ureq::Error::Status(code, res)) => {
+                let status = Status::try_from(code).map_err(|e| HttpErr::Other(e.to_string()))?;
+                // the http date form is rare for rpc nodes and ignored
+                let retry_after = res
+                    .header("retry-after")
+                    .and_then(|v| v.trim().parse().ok())
+                    .map(Duration::from_secs);
+                if [429, 503].contains(status.deref()) {
+                    return Err(HttpErr::Busy {
+                        url: self.url.clone(),
+                        status,
+                        retry_after,
+                    });
+                }
+                return Err(HttpErr::FailStatus(self.url.to_string(), status));
+            }
+            Err(e) => return Err(HttpErr::IO(self.transport_err(e))),
+        };
+        let mut body = Vec::new();
+        res.into_reader()
+            .read_to_end(&mut body)
+            .map_err(HttpErr::BodyIO)?;
+        Ok(body)
+    }
 
-        Ok(res)
+    /// ureq puts the full url into its errors, rebuilds the message around the redacted one
+    fn transport_err(&self, e: ureq::Error) -> std::io::Error {
+        let mut msg = self.url.to_string();
+        if let ureq::Error::Transport(t) = &e {
+            msg += &format!(": {}", t.kind());
+            if let Some(m) = t.message() {
+                msg += &format!(": {}", m);
+            }
+        }
+        let source = std::error::Error::source(&e);
+        if let Some(source) = source {
+            msg += &format!(": {}", source);
+        }
+        let kind = source
+            .and_then(|s| s.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind())
+            .unwrap_or(std::io::ErrorKind::Other);
+        std::io::Error::new(kind, msg)
     }
 
-    pub(crate) fn new(http: &str) -> Result<Self, String> {
-        Url::parse(http).map_err(|e| {
+    /// url parses and headers are valid
+    fn validate(&self) -> Result<(), String> {
+        Url::parse(self.url.expose()).map_err(|e| {
             format!(
                 "Failed to parse http transport url = {} err = {:?}",
-                http, e
+                self.url, e
             )
         })?;
-        Ok(Self(http.to_string()))
+        for (name, value) in self.headers.iter() {
+            HeaderName::try_from(name.as_str()).map_err(|e| format!("{} {:?}", name, e))?;
+            HeaderValue::try_from(value.as_str()).map_err(|e| format!("{} {:?}", name, e))?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            limit.validate()?;
+        }
+        Ok(())
+    }
+
+    /// default options
+    fn with_url(url: impl Into<SecretUrl>) -> Self {
+        Self {
+            url: url.into(),
+            connect_timeout_millis: default_timeout_millis(),
+            read_timeout_millis: default_timeout_millis(),
+            headers: BTreeMap::new(),
+            auth: None,
+            rate_limit: None,
+            circuit_breaker: None,
+            circuit: SharedCircuit::default(),
+        }
+    }
+
+    /// default options, fails if the url doesn't parse
+    pub fn new(http: &str) -> Result<Self, String> {
+        let http = Self::with_url(http.to_string());
+        http.validate()?;
+        Ok(http)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::EnvHttp;
+    use crate::{
+        http_mock::{serve, MockResponse},
+        EnvHttp, JRError, RpcTransportErr,
+    };
     use serde_json::json;
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
 
     #[test]
     fn test_deserialize() {
         assert!(HttpTransport::new("").is_err());
         println!("{:?}", HttpTransport::new("https://etherscan.io/"));
         assert!(HttpTransport::new("https://etherscan.io/").is_ok());
+        assert_eq!(
+            HttpTransport::try_from(String::from("https://etherscan.io/")),
+            HttpTransport::new("https://etherscan.io/")
+        );
+        assert!(HttpTransport::try_from(String::from("etherscan")).is_err());
+    }
+
+    #[test]
+    fn test_serde_config() {
+        // plain string stays a plain string
+        let http: HttpTransport =
+            serde_json::from_value(json!("https://rpc.ankr.com/eth")).unwrap();
+        assert_eq!(
+            serde_json::to_value(&http).unwrap(),
+            json!("https://rpc.ankr.com/eth")
+        );
+        let config = json!({
+            "url": "https://rpc.ankr.com/eth",
+            "connect_timeout_millis": 1000,
+            "read_timeout_millis": 10000,
+            "headers": {"x-api-key": "key"},
+            "auth": {"Basic": {"username": "user", "password": "pass"}}
+        });
+        let http: HttpTransport = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(http.connect_timeout_millis, 1000);
+        assert_eq!(serde_json::to_value(&http).unwrap(), config);
+        assert!(serde_json::from_value::<HttpTransport>(json!("")).is_err());
+        assert!(serde_json::from_value::<HttpTransport>(json!({
+            "url": "https://rpc.ankr.com/eth",
+            "headers": {"bad header": "key"}
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_headers_and_auth() {
+        let url = serve(|req| {
+            let expected = [
+                ("x-api-key", "key"),
+                ("authorization", "Basic dXNlcjpwYXNz"),
+            ];
+            for (k, v) in expected {
+                if req.headers.get(k).map(|h| h.as_str()) != Some(v) {
+                    return MockResponse::status(401, "");
+                }
+            }
+            MockResponse::ok("{}")
+        });
+        let http = HttpTransport::new(&url).unwrap();
+        assert!(http.post(b"{}").is_err());
+        let http = http
+            .with_header("x-api-key", "key")
+            .unwrap()
+            .with_auth(HttpAuth::Basic {
+                username: "user".into(),
+                password: "pass".into(),
+            });
+        assert_eq!(http.post(b"{}").unwrap(), b"{}");
+        assert_eq!(HttpAuth::Bearer("jwt".into()).header_value(), "Bearer jwt");
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let url = serve(|_| {
+            std::thread::sleep(Duration::from_millis(300));
+            MockResponse::ok("{}")
+        });
+        let http = HttpTransport::new(&url).unwrap().with_timeouts(1_000, 50);
+        assert!(matches!(http.post(b"{}"), Err(HttpErr::IO(_))));
+        let http = http.with_timeouts(1_000, 1_000);
+        assert!(http.post(b"{}").is_ok());
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let conns = Arc::new(Mutex::new(HashSet::new()));
+        let inner = conns.clone();
+        let url = serve(move |req| {
+            inner.lock().unwrap().insert(req.conn);
+            MockResponse::ok(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+        });
+        let transport = HttpTransport::new(&url).unwrap();
+        for _ in 0..20 {
+            transport.clone().post(b"{}").unwrap();
+        }
+        assert_eq!(conns.lock().unwrap().len(), 1);
+        // clones share the pool across threads
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let transport = transport.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        transport.post(b"{}").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(conns.lock().unwrap().len() <= 9);
+    }
+
+    #[test]
+    fn test_redacted_errors() {
+        let key = "aBcD1234efGH5678ijKL9012mnOP3456";
+        let url = serve(|_| MockResponse::status(403, ""));
+        let http = HttpTransport::new(&format!("{}v2/{}", url, key))
+            .unwrap()
+            .with_header("x-api-key", key)
+            .unwrap()
+            .with_auth(HttpAuth::Bearer(key.into()));
+        let err = JRError::Transport(RpcTransportErr::Http(http.post(b"{}").unwrap_err()));
+        assert!(format!("{:?}", err).contains("v2/***"));
+        assert!(!format!("{:?} {:?}", err, http).contains(key));
+        // nothing listens on port 1
+        let http = HttpTransport::new(&format!("http://127.0.0.1:1/v2/{}", key)).unwrap();
+        let err = http.post(b"{}").unwrap_err();
+        assert!(
+            matches!(&err, HttpErr::IO(e) if e.kind() == std::io::ErrorKind::ConnectionRefused)
+        );
+        assert!(!format!("{:?}", err).contains(key));
+        // the real thing is still serialized
+        assert!(serde_json::to_string(&http).unwrap().contains(key));
+    }
+
+    #[test]
+    fn test_fail_status() {
+        let url = serve(|_| MockResponse::status(429, "slow down").header("retry-after", "2"));
+        let err = HttpTransport::new(&url).unwrap().post(b"{}").unwrap_err();
+        assert!(matches!(
+            err,
+            HttpErr::Busy { status, retry_after: Some(after), .. }
+                if *status == 429 && after == Duration::from_secs(2)
+        ));
+        let url = serve(|_| {
+            MockResponse::status(503, "down").header("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")
+        });
+        let err = HttpTransport::new(&url).unwrap().post(b"{}").unwrap_err();
+        assert!(matches!(
+            err,
+            HttpErr::Busy { status, retry_after: None, .. } if *status == 503
+        ));
+        let key = "aBcD1234efGH5678ijKL9012mnOP3456";
+        let url = serve(|_| MockResponse::status(500, "oops"));
+        let err = HttpTransport::new(&format!("{}v2/{}", url, key))
+            .unwrap()
+            .post(b"{}")
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            HttpErr::FailStatus(url, status) if url.ends_with("v2/***") && **status == 500
+        ));
+        assert_eq!(err.status().map(|s| *s), Some(500));
     }
 
     #[test]
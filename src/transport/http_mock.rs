@@ -0,0 +1,164 @@
+//! local http server for transport tests
+#![allow(dead_code)]
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+pub struct MockRequest {
+    /// index of the tcp connection the request came in on
+    pub conn: usize,
+    pub method: String,
+    pub path: String,
+    /// lowercase names
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn ok(body: impl ToString) -> Self {
+        Self::status(200, body)
+    }
+    pub fn status(status: u16, body: impl ToString) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// serves every connection on its own thread, honours keep-alive, returns the base url
+pub fn serve<F>(handler: F) -> String
+where
+    F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    std::thread::spawn(move || {
+        for (conn, stream) in listener.incoming().enumerate() {
+            let handler = handler.clone();
+            std::thread::spawn(move || handle(conn, stream.unwrap(), handler.as_ref()));
+        }
+    });
+    url
+}
+
+/// `json rpc` responder echoing the request id(s) with a fixed result, batches included
+pub fn serve_result(result: serde_json::Value) -> String {
+    serve(move |req| {
+        let call: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+        let res = |call: &serde_json::Value| serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": result});
+        match call.as_array() {
+            Some(calls) => MockResponse::ok(serde_json::Value::from_iter(calls.iter().map(res))),
+            None => MockResponse::ok(res(&call)),
+        }
+    })
+}
+
+/// single calls sent by a `recording` client, in order
+pub type Recorded = Arc<Mutex<Vec<serde_json::Value>>>;
+
+/// client of a node answering every single call with `respond(call)`, a result or an error object
+pub fn recording<F>(respond: F) -> (crate::EthRpc, Recorded)
+where
+    F: Fn(&serde_json::Value) -> Result<serde_json::Value, serde_json::Value>
+        + Send
+        + Sync
+        + 'static,
+{
+    let recorded = Recorded::default();
+    let inner = recorded.clone();
+    let url = serve(move |req| {
+        let call: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+        inner.lock().unwrap().push(call.clone());
+        let (key, value) = match respond(&call) {
+            Ok(result) => ("result", result),
+            Err(error) => ("error", error),
+        };
+        MockResponse::ok(serde_json::json!({"jsonrpc": "2.0", "id": call["id"], key: value}))
+    });
+    (crate::EthRpc::with_http(url).unwrap(), recorded)
+}
+
+/// `params` of every recorded call
+pub fn recorded_params(recorded: &Recorded) -> Vec<serde_json::Value> {
+    let calls = recorded.lock().unwrap();
+    calls.iter().map(|c| c["params"].clone()).collect()
+}
+
+fn handle<F>(conn: usize, stream: TcpStream, handler: &F)
+where
+    F: Fn(&MockRequest) -> MockResponse,
+{
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+            }
+        }
+        let len: usize = headers
+            .get("content-length")
+            .map(|l| l.parse().unwrap())
+            .unwrap_or(0);
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        let close = headers
+            .get("connection")
+            .map(|c| c.eq_ignore_ascii_case("close"))
+            .unwrap_or(false);
+        let req = MockRequest {
+            conn,
+            method,
+            path,
+            headers,
+            body,
+        };
+        let res = handler(&req);
+        let mut out = format!(
+            "HTTP/1.1 {} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\n",
+            res.status,
+            res.body.len()
+        );
+        for (k, v) in res.headers.iter() {
+            out += &format!("{}: {}\r\n", k, v);
+        }
+        if close {
+            out += "connection: close\r\n";
+        }
+        out += "\r\n";
+        out += &res.body;
+        if writer.write_all(out.as_bytes()).is_err() || close {
+            return;
+        }
+    }
+}
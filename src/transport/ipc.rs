@@ -1,104 +1,361 @@
+use super::multiplex::{
+    Connector, Multiplexer, MuxErr, SocketReader, SocketWriter, Subscribed, SOCKET_READ_TIMEOUT,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
-    io::{Read, Write},
-    net::Shutdown,
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
     os::unix::net::UnixStream,
+    sync::{Arc, Mutex, OnceLock},
     time::Duration,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IpcConfig {
     pub ipc_path: String,
+    /// how long to wait for a response
     pub read_timeout_millis: u64,
 }
 
 #[derive(Debug)]
 pub enum IpcError {
     IO(std::io::Error),
+    #[deprecated(note = "never returned, a closed connection is `Disconnected`")]
     EOF,
+    InvalidRequest(serde_json::Error),
+    /// no response within `read_timeout_millis`
+    Timeout,
+    /// node closed the connection or it failed while waiting, next request reconnects
+    Disconnected,
+}
+
+#[allow(deprecated)]
+impl IpcError {
+    /// stable name for grouping in logs and alerts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::IO(_) => "ipc_io",
+            Self::EOF => "ipc_eof",
+            Self::InvalidRequest(_) => "ipc_invalid_request",
+            Self::Timeout => "ipc_timeout",
+            Self::Disconnected => "ipc_disconnected",
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(_) => write!(f, "ipc io failed"),
+            Self::EOF => write!(f, "ipc connection closed"),
+            Self::InvalidRequest(_) => write!(f, "invalid ipc request"),
+            Self::Timeout => write!(f, "ipc request timed out"),
+            Self::Disconnected => write!(f, "ipc connection dropped"),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            Self::InvalidRequest(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MuxErr<std::io::Error>> for IpcError {
+    fn from(e: MuxErr<std::io::Error>) -> Self {
+        match e {
+            MuxErr::Connect(e) => Self::IO(e),
+            MuxErr::InvalidRequest(e) => Self::InvalidRequest(e),
+            MuxErr::Timeout => Self::Timeout,
+            MuxErr::Disconnected => Self::Disconnected,
+        }
+    }
 }
 
 impl IpcConfig {
+    pub fn new(ipc_path: impl ToString, read_timeout_millis: u64) -> Self {
+        Self {
+            ipc_path: ipc_path.to_string(),
+            read_timeout_millis,
+        }
+    }
+
+    pub(crate) fn subscribe(&self, params: Value) -> Result<Subscribed, IpcError> {
+        Ok(self.multiplexer().subscribe(params)?)
+    }
+
+    pub(crate) fn unsubscribe(&self, handle: u64) {
+        self.multiplexer().unsubscribe(handle)
+    }
+
+    /// persistent connection, process wide and keyed by path and timeout like the http agents,
+    /// so every `IpcConfig` and its clones share it
+    fn multiplexer(&self) -> Arc<Multiplexer<IpcConnector>> {
+        type Conns = HashMap<(String, u64), Arc<Multiplexer<IpcConnector>>>;
+        static CONNS: OnceLock<Mutex<Conns>> = OnceLock::new();
+        let mut conns = CONNS.get_or_init(Default::default).lock().unwrap();
+        conns
+            .entry((self.ipc_path.clone(), self.read_timeout_millis))
+            .or_insert_with(|| {
+                Arc::new(Multiplexer::new(
+                    IpcConnector {
+                        ipc_path: self.ipc_path.clone(),
+                    },
+                    Duration::from_millis(self.read_timeout_millis),
+                ))
+            })
+            .clone()
+    }
+
+    /// connects lazily on first use and reconnects if the node restarted
     pub fn send_ipc(&self, params: &[u8]) -> Result<Vec<u8>, IpcError> {
-        let mut stream = UnixStream::connect(self.ipc_path.clone()).map_err(|e| IpcError::IO(e))?;
-        stream
-            .set_read_timeout(Some(
-                Duration::from_millis(self.read_timeout_millis).clone(),
-            ))
-            .map_err(|e| IpcError::IO(e))?;
-        stream.write_all(params).map_err(|e| IpcError::IO(e))?;
-        let response = get_ipc_response(&mut stream)?;
-        stream
-            .shutdown(Shutdown::Both)
-            .map_err(|e| IpcError::IO(e))?;
-        Ok(response)
+        Ok(self.multiplexer().send(params)?)
     }
 }
 
-/// linux kernel pipe read size
-const DEFAULT_KERNEL_READ_SIZE: usize = 128 * 1024;
+struct IpcConnector {
+    ipc_path: String,
+}
+
+impl Connector for IpcConnector {
+    type Reader = IpcSocket;
+    type Writer = IpcWriter;
+    type Error = std::io::Error;
+    fn connect(&self) -> Result<(IpcSocket, IpcWriter), std::io::Error> {
+        let stream = UnixStream::connect(&self.ipc_path)?;
+        let writer = IpcWriter(stream.try_clone()?);
+        Ok((IpcSocket::new(stream)?, writer))
+    }
+}
+
+struct IpcWriter(UnixStream);
+
+impl SocketWriter for IpcWriter {
+    fn write_msg(&mut self, msg: &[u8]) -> std::io::Result<()> {
+        self.0.write_all(msg)
+    }
+}
+
+/// ipc has no message boundaries, so messages are cut out of the byte stream
+/// instead of guessing the end from short reads
+struct IpcSocket<const N: usize = DEFAULT_KERNEL_READ_SIZE> {
+    stream: UnixStream,
+    framer: JsonFramer,
+    /// `N` sized read buffer
+    read_buf: Vec<u8>,
+}
 
-fn get_ipc_response(stream: &mut UnixStream) -> Result<Vec<u8>, IpcError> {
-    get_ipc_response_with_read_size::<DEFAULT_KERNEL_READ_SIZE>(stream)
-}
-
-fn get_ipc_response_with_read_size<const N: usize>(
-    stream: &mut UnixStream,
-) -> Result<Vec<u8>, IpcError> {
-    let mut response = Vec::new();
-    // default kernel size buffer for reads
-    let buf = &mut [0; N];
-    let mut counter = 0;
-    loop {
-        // handle read
-        let bytes_read = stream.read(buf).map_err(|e| IpcError::IO(e))?;
-        // println!("bytes read {}", bytes_read);
-        if bytes_read == 0 {
-            // if we read nothing then say eof
-            if counter == 0 {
-                // println!("eof?");
-                return Err(IpcError::EOF);
+impl<const N: usize> IpcSocket<N> {
+    fn new(stream: UnixStream) -> std::io::Result<Self> {
+        stream.set_read_timeout(Some(SOCKET_READ_TIMEOUT))?;
+        Ok(Self {
+            stream,
+            framer: JsonFramer::default(),
+            read_buf: vec![0; N],
+        })
+    }
+}
+
+impl<const N: usize> SocketReader for IpcSocket<N> {
+    fn read_msg(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(msg) = self.framer.next_msg() {
+                return Ok(Some(msg));
+            }
+            match self.stream.read(&mut self.read_buf) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(n) => self.framer.extend(&self.read_buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
             }
-            // data might be valid, might be invalid, no way of knowing since
-            // it is multiple of `N`
-            // println!("eof msg end");
-            return Ok(response);
         }
-        counter += 1;
-        response.extend(buf[0..bytes_read].iter());
-        if bytes_read != N {
-            // println!("msg end");
-            return Ok(response);
+    }
+}
+
+/// splits a stream of concatenated json objects/arrays,
+/// only looks at every byte once no matter how the stream is chunked
+#[derive(Default)]
+struct JsonFramer {
+    buf: Vec<u8>,
+    /// start of the current message in `buf`
+    start: usize,
+    /// bytes of `buf` already scanned
+    scanned: usize,
+    depth: usize,
+    in_str: bool,
+    escaped: bool,
+}
+
+impl JsonFramer {
+    fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn next_msg(&mut self) -> Option<Vec<u8>> {
+        while self.scanned < self.buf.len() {
+            let b = self.buf[self.scanned];
+            self.scanned += 1;
+            if self.in_str {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_str = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_str = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        let msg = self.buf[self.start..self.scanned].to_vec();
+                        self.buf.drain(..self.scanned);
+                        self.start = 0;
+                        self.scanned = 0;
+                        return Some(msg);
+                    }
+                }
+                // whitespace or newlines between messages
+                _ if self.depth == 0 => self.start = self.scanned,
+                _ => {}
+            }
         }
+        None
     }
 }
 
+/// linux kernel pipe read size
+const DEFAULT_KERNEL_READ_SIZE: usize = 128 * 1024;
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::{thread::sleep, time::Duration};
+    use crate::{EthRpc, JRCall, RpcTransport};
+    use serde_json::json;
+    use std::{os::unix::net::UnixListener, thread::sleep};
+
+    /// json rpc response of exactly `len` bytes
+    fn response_of_len(id: u64, len: usize) -> Vec<u8> {
+        let empty = json!({"jsonrpc": "2.0", "id": id, "result": ""}).to_string();
+        let padding = "a".repeat(len - empty.len());
+        let res = json!({"jsonrpc": "2.0", "id": id, "result": padding}).to_string();
+        assert_eq!(res.len(), len);
+        res.into_bytes()
+    }
 
-    fn run_test_chunk<const N: usize>() {
-        let dummy_data: Vec<u8> = vec![1; 10];
-        let (mut sock0, mut sock1) = UnixStream::pair().unwrap();
+    /// writes `data` in one go, expects the messages back in order
+    fn run_test_chunk<const N: usize>(msgs: Vec<Vec<u8>>) {
+        let (sock0, mut sock1) = UnixStream::pair().unwrap();
 
-        let inner_data = dummy_data.clone();
+        let inner_data = msgs.concat();
         let handle1 = std::thread::spawn(move || {
             sleep(Duration::from_millis(100));
             sock1.write_all(&inner_data)
         });
 
-        let handle0 = std::thread::spawn(move || get_ipc_response_with_read_size::<N>(&mut sock0));
-
-        let res = handle0.join().unwrap().unwrap();
-        println!("write res {:?}", handle1.join());
-        println!("got res {:?}", res);
-        assert_eq!(dummy_data, res);
+        let mut socket = IpcSocket::<N>::new(sock0).unwrap();
+        let mut res = Vec::new();
+        while res.len() < msgs.len() {
+            if let Some(msg) = socket.read_msg().unwrap() {
+                res.push(msg);
+            }
+        }
+        handle1.join().unwrap().unwrap();
+        assert_eq!(msgs, res);
     }
 
     #[test]
     fn test_ipc_chunked_data() {
-        run_test_chunk::<2>();
-        run_test_chunk::<3>();
+        run_test_chunk::<2>(vec![response_of_len(1, 100)]);
+        run_test_chunk::<3>(vec![response_of_len(1, 100), response_of_len(2, 101)]);
+        // exact multiples of the read size used to be indistinguishable from a partial read
+        run_test_chunk::<64>(vec![response_of_len(1, 64 * 4), response_of_len(2, 64)]);
+        run_test_chunk::<DEFAULT_KERNEL_READ_SIZE>(vec![
+            response_of_len(1, DEFAULT_KERNEL_READ_SIZE * 2),
+            response_of_len(2, DEFAULT_KERNEL_READ_SIZE),
+        ]);
+        // large
+        run_test_chunk::<DEFAULT_KERNEL_READ_SIZE>(vec![response_of_len(1, 10_000_000)]);
+    }
+
+    /// answers with `method.len() * 64KiB` long responses in reverse order, hangs up after `per_conn` requests
+    fn mock_node(name: &str, per_conn: usize) -> String {
+        let path =
+            std::env::temp_dir().join(format!("eth_rpc_{}_{}.ipc", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let reqs: Vec<Value> = serde_json::Deserializer::from_reader(&stream)
+                    .into_iter()
+                    .take(per_conn)
+                    .map(|r| r.unwrap())
+                    .collect();
+                for req in reqs.iter().rev() {
+                    let call = &req["method"];
+                    let len = 64 * 1024 * call.as_str().unwrap().len();
+                    let mut res: Value = serde_json::from_slice(&response_of_len(0, len)).unwrap();
+                    res["id"] = req["id"].clone();
+                    stream.write_all(res.to_string().as_bytes()).unwrap();
+                }
+            }
+        });
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_ipc_in_flight_and_reconnect() {
+        let ipc = IpcConfig::new(mock_node("mux", 4), 5_000);
+        let client = EthRpc::with_transport(RpcTransport::Ipc(ipc));
+        // two rounds, the node hangs up after each one
+        for _ in 0..2 {
+            let handles: Vec<_> = (1..=4)
+                .map(|i| {
+                    let client = client.clone();
+                    std::thread::spawn(move || {
+                        let method = "m".repeat(i);
+                        let res: String = client
+                            .no_ratelimit_rpc(JRCall::new(method, Vec::new() as Vec<()>).unwrap())
+                            .unwrap();
+                        assert!(res.len() > 64 * 1024 * (i - 1));
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.join().unwrap();
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_json_framer() {
+        let mut framer = JsonFramer::default();
+        let stream = br#"{"id":1,"result":"}\"{"}
+[{"id":2,"result":[1,{"a":[]}]}]  {"id":3"#;
+        // worst case chunking, one byte at a time
+        let mut msgs = Vec::new();
+        for b in stream.iter() {
+            framer.extend(&[*b]);
+            while let Some(msg) = framer.next_msg() {
+                msgs.push(serde_json::from_slice::<Value>(&msg).unwrap());
+            }
+        }
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0]["result"], "}\"{");
+        assert_eq!(msgs[1][0]["result"][1]["a"], serde_json::json!([]));
+        framer.extend(b"}");
+        assert!(framer.next_msg().is_some());
     }
 }
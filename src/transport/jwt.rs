@@ -0,0 +1,114 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// engine api secret, the 32 byte hex file geth/reth write with `--authrpc.jwtsecret`
+///
+/// serialized as the file path, the key itself is never printed
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JwtSecret {
+    pub path: String,
+    key: [u8; 32],
+}
+
+impl std::fmt::Debug for JwtSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtSecret")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl TryFrom<String> for JwtSecret {
+    type Error = String;
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Self::from_file(path)
+    }
+}
+
+impl From<JwtSecret> for String {
+    fn from(value: JwtSecret) -> Self {
+        value.path
+    }
+}
+
+impl JwtSecret {
+    pub fn from_file(path: impl ToString) -> Result<Self, String> {
+        let path = path.to_string();
+        let hex = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read jwt secret {} err = {:?}", path, e))?;
+        let hex = hex.trim();
+        let bytes = ethers::utils::hex::decode(hex.strip_prefix("0x").unwrap_or(hex))
+            .map_err(|e| format!("Jwt secret {} is not hex err = {:?}", path, e))?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| format!("Jwt secret {} is not 32 bytes", path))?;
+        Ok(Self { path, key })
+    }
+
+    /// HS256 token with the current `iat`, nodes reject ones older than 60s
+    pub fn token(&self) -> String {
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time after epoch")
+            .as_secs();
+        self.token_at(iat)
+    }
+
+    fn token_at(&self, iat: u64) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(format!(r#"{{"iat":{}}}"#, iat));
+        let msg = format!("{}.{}", header, claims);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key size");
+        mac.update(msg.as_bytes());
+        let sig = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", msg, sig)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// writes `content` to a temp file
+    fn secret_file(name: &str, content: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("eth_rpc_{}_{}.hex", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_from_file() {
+        let hex = "0x".to_string() + &"ab".repeat(32) + "\n";
+        let secret = JwtSecret::from_file(secret_file("jwt_ok", &hex)).unwrap();
+        assert_eq!(secret.key, [0xab; 32]);
+        assert!(!format!("{:?}", secret).contains("abab"));
+        let json = serde_json::to_value(&secret).unwrap();
+        assert_eq!(serde_json::from_value::<JwtSecret>(json).unwrap(), secret);
+        assert!(JwtSecret::from_file(secret_file("jwt_short", "abab")).is_err());
+        assert!(JwtSecret::from_file("/nonexistent/jwt.hex").is_err());
+    }
+
+    #[test]
+    fn test_token() {
+        let secret = JwtSecret {
+            path: String::new(),
+            key: [0x11; 32],
+        };
+        let token = secret.token_at(1_700_000_000);
+        let parts: Vec<&str> = token.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            URL_SAFE_NO_PAD.decode(parts[1]).unwrap(),
+            br#"{"iat":1700000000}"#
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(&[0x11; 32]).unwrap();
+        mac.update(format!("{}.{}", parts[0], parts[1]).as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(parts[2]).unwrap())
+            .unwrap();
+    }
+}
@@ -1,8 +1,27 @@
+mod balanced;
+mod circuit_breaker;
 mod env_http;
+mod failover;
 mod http;
+#[cfg(test)]
+pub(crate) mod http_mock;
 mod ipc;
+mod jwt;
+mod multiplex;
+mod quorum;
+mod rate_limit;
+mod secret_url;
 mod transport;
+mod ws;
+pub use balanced::{BalancedConfig, EndpointStats, Selection};
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
 pub use env_http::EnvHttp;
-pub use http::{HttpErr, HttpTransport};
+pub use failover::FailoverConfig;
+pub use http::{HttpAuth, HttpErr, HttpTransport};
 pub use ipc::{IpcConfig, IpcError};
-pub use transport::{RpcTransport, RpcTransportErr};
+pub use jwt::JwtSecret;
+pub use quorum::{NoQuorum, QuorumConfig};
+pub use rate_limit::{compute_units, RateLimit, RateUnit};
+pub use secret_url::SecretUrl;
+pub use transport::{RpcTransport, RpcTransportErr, Transport};
+pub use ws::{WsConfig, WsError};
@@ -0,0 +1,463 @@
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// how long a reader blocks before checking if the connection was closed on our side,
+/// arriving messages wake it up right away
+pub(crate) const SOCKET_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// receiving half of a message oriented connection, owned by the reader thread
+pub(crate) trait SocketReader: Send + 'static {
+    /// `Ok(None)` when nothing arrived within `SOCKET_READ_TIMEOUT`
+    fn read_msg(&mut self) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+/// sending half, owned by the writer thread
+pub(crate) trait SocketWriter: Send + 'static {
+    fn write_msg(&mut self, msg: &[u8]) -> std::io::Result<()>;
+}
+
+pub(crate) trait Connector: Send + Sync + 'static {
+    type Reader: SocketReader;
+    type Writer: SocketWriter;
+    type Error: Debug;
+    fn connect(&self) -> Result<(Self::Reader, Self::Writer), Self::Error>;
+}
+
+#[derive(Debug)]
+pub(crate) enum MuxErr<E> {
+    Connect(E),
+    InvalidRequest(serde_json::Error),
+    Timeout,
+    Disconnected,
+}
+
+type Pending = Arc<Mutex<HashMap<u64, Sender<Value>>>>;
+
+/// local handle, notification receiver and the raw `eth_subscribe` response
+pub(crate) type Subscribed = (u64, Receiver<Value>, Vec<u8>);
+
+struct Sub {
+    /// `eth_subscribe` params, replayed after reconnect
+    params: Value,
+    server_id: Option<String>,
+    tx: Sender<Value>,
+}
+
+/// outlives connections so subscriptions survive reconnects
+#[derive(Default)]
+struct Subs {
+    /// local handle -> subscription
+    active: HashMap<u64, Sub>,
+    /// server subscription id -> local handle
+    routes: HashMap<String, u64>,
+    /// in flight `eth_subscribe` request id -> local handle
+    subscribing: HashMap<u64, u64>,
+}
+
+type SharedSubs = Arc<Mutex<Subs>>;
+
+/// cap on the reconnect backoff while subscriptions wait for a connection
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+struct Live {
+    outgoing: Sender<Vec<u8>>,
+    pending: Pending,
+    alive: Arc<AtomicBool>,
+}
+
+/// one persistent connection shared by every caller,
+/// requests get connection unique ids so many can be in flight at once,
+/// lives in an `Arc` so the reader thread can reconnect on its own for subscriptions
+pub(crate) struct Multiplexer<C> {
+    connector: C,
+    timeout: Duration,
+    live: Mutex<Option<Live>>,
+    subs: SharedSubs,
+    next_id: AtomicU64,
+}
+
+impl<C> Debug for Multiplexer<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Multiplexer")
+            .field("connected", &self.is_connected())
+            .field("subscriptions", &self.subs.lock().unwrap().active.len())
+            .finish()
+    }
+}
+
+impl<C> Multiplexer<C> {
+    pub fn is_connected(&self) -> bool {
+        let live = self.live.lock().unwrap();
+        live.as_ref()
+            .map(|l| l.alive.load(Ordering::Acquire))
+            .unwrap_or(false)
+    }
+}
+
+impl<C: Connector> Multiplexer<C> {
+    pub fn new(connector: C, timeout: Duration) -> Self {
+        Self {
+            connector,
+            timeout,
+            live: Mutex::new(None),
+            subs: Default::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// send single or batch request and wait for all of its responses
+    pub fn send(self: &Arc<Self>, params: &[u8]) -> Result<Vec<u8>, MuxErr<C::Error>> {
+        let request: Value = serde_json::from_slice(params).map_err(MuxErr::InvalidRequest)?;
+        self.send_value(request, None)
+    }
+
+    pub fn subscribe(self: &Arc<Self>, params: Value) -> Result<Subscribed, MuxErr<C::Error>> {
+        let handle = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.subs.lock().unwrap().active.insert(
+            handle,
+            Sub {
+                params: params.clone(),
+                server_id: None,
+                tx,
+            },
+        );
+        let request =
+            json!({"jsonrpc": "2.0", "id": 0, "method": "eth_subscribe", "params": params});
+        match self.send_value(request, Some(handle)) {
+            Ok(response) => Ok((handle, rx, response)),
+            Err(e) => {
+                self.unsubscribe(handle);
+                Err(e)
+            }
+        }
+    }
+
+    /// forgets the subscription, tells the node without waiting for its answer
+    pub fn unsubscribe(&self, handle: u64) {
+        let server_id = {
+            let mut subs = self.subs.lock().unwrap();
+            subs.subscribing.retain(|_, h| *h != handle);
+            let server_id = subs.active.remove(&handle).and_then(|s| s.server_id);
+            if let Some(server_id) = &server_id {
+                subs.routes.remove(server_id);
+            }
+            server_id
+        };
+        let live = self.live.lock().unwrap().clone();
+        if let (Some(server_id), Some(live)) = (server_id, live) {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let _ = live.outgoing.send(
+                json!({"jsonrpc": "2.0", "id": id, "method": "eth_unsubscribe", "params": [server_id]})
+                    .to_string()
+                    .into_bytes(),
+            );
+        }
+    }
+
+    fn send_value(
+        self: &Arc<Self>,
+        mut request: Value,
+        subscription: Option<u64>,
+    ) -> Result<Vec<u8>, MuxErr<C::Error>> {
+        let is_batch = request.is_array();
+        // swap caller ids (usually all `0`) for connection unique ones
+        let mut original_ids = HashMap::new();
+        for call in calls_mut(&mut request) {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let original = call
+                .as_object_mut()
+                .and_then(|c| c.insert("id".into(), json!(id)))
+                .unwrap_or(Value::Null);
+            original_ids.insert(id, original);
+        }
+        let ids: Vec<u64> = original_ids.keys().copied().collect();
+        let msg = serde_json::to_vec(&request).map_err(MuxErr::InvalidRequest)?;
+        if let Some(handle) = subscription {
+            // reader thread links the server id before any notification can be routed
+            let mut subs = self.subs.lock().unwrap();
+            ids.iter().for_each(|id| {
+                subs.subscribing.insert(*id, handle);
+            });
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let Live {
+            outgoing, pending, ..
+        } = self.register(&ids, tx)?;
+        if outgoing.send(msg).is_err() {
+            unregister(&pending, &ids);
+            return Err(MuxErr::Disconnected);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut responses = Vec::with_capacity(ids.len());
+        while responses.len() < ids.len() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut response = match rx.recv_timeout(left) {
+                Ok(v) => v,
+                Err(RecvTimeoutError::Timeout) => {
+                    unregister(&pending, &ids);
+                    return Err(MuxErr::Timeout);
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(MuxErr::Disconnected),
+            };
+            // give the caller back its own id
+            let id = response.get("id").and_then(Value::as_u64);
+            if let (Some(id), Some(obj)) = (id, response.as_object_mut()) {
+                if let Some(original) = original_ids.get(&id) {
+                    obj.insert("id".into(), original.clone());
+                }
+            }
+            responses.push(response);
+        }
+        let response = if is_batch {
+            Value::Array(responses)
+        } else {
+            responses.remove(0)
+        };
+        serde_json::to_vec(&response).map_err(MuxErr::InvalidRequest)
+    }
+
+    fn register(
+        self: &Arc<Self>,
+        ids: &[u64],
+        tx: Sender<Value>,
+    ) -> Result<Live, MuxErr<C::Error>> {
+        let live = self.live()?;
+        let mut p = live.pending.lock().unwrap();
+        // reader thread died between `live` and here, its cleanup would never see us
+        if !live.alive.load(Ordering::Acquire) {
+            return Err(MuxErr::Disconnected);
+        }
+        for id in ids {
+            p.insert(*id, tx.clone());
+        }
+        drop(p);
+        Ok(live)
+    }
+
+    /// current connection, reconnects if the previous one dropped
+    fn live(self: &Arc<Self>) -> Result<Live, MuxErr<C::Error>> {
+        let mut live = self.live.lock().unwrap();
+        if let Some(l) = live.as_ref() {
+            if l.alive.load(Ordering::Acquire) {
+                return Ok(l.clone());
+            }
+            debug!("Connection dropped, reconnecting.");
+        }
+        let (reader, writer) = self.connector.connect().map_err(MuxErr::Connect)?;
+        let (outgoing, outgoing_rx) = mpsc::channel();
+        let pending = Pending::default();
+        let alive = Arc::new(AtomicBool::new(true));
+        {
+            let pending = pending.clone();
+            let alive = alive.clone();
+            let mux = Arc::downgrade(self);
+            std::thread::spawn(move || run(reader, writer, outgoing_rx, pending, alive, mux));
+        }
+        let l = Live {
+            outgoing,
+            pending,
+            alive,
+        };
+        self.resubscribe(&l);
+        *live = Some(l.clone());
+        Ok(l)
+    }
+
+    /// server ids die with the connection, replay every `eth_subscribe`
+    fn resubscribe(&self, live: &Live) {
+        let mut subs = self.subs.lock().unwrap();
+        let Subs {
+            active,
+            routes,
+            subscribing,
+        } = &mut *subs;
+        routes.clear();
+        // first time subscribers are about to send their own request
+        let in_flight: HashSet<u64> = subscribing.values().copied().collect();
+        for (handle, sub) in active.iter_mut().filter(|(h, _)| !in_flight.contains(h)) {
+            debug!("Resubscribing to {}", sub.params);
+            sub.server_id = None;
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            subscribing.insert(id, *handle);
+            let _ = live.outgoing.send(
+                json!({"jsonrpc": "2.0", "id": id, "method": "eth_subscribe", "params": sub.params})
+                    .to_string()
+                    .into_bytes(),
+            );
+        }
+    }
+}
+
+fn calls_mut(request: &mut Value) -> Vec<&mut Value> {
+    match request {
+        Value::Array(calls) => calls.iter_mut().collect(),
+        call => vec![call],
+    }
+}
+
+fn unregister(pending: &Pending, ids: &[u64]) {
+    let mut p = pending.lock().unwrap();
+    for id in ids {
+        p.remove(id);
+    }
+}
+
+/// reader thread, spawns the writer, exits when the socket fails or every handle is dropped
+fn run<C: Connector>(
+    mut reader: C::Reader,
+    writer: C::Writer,
+    outgoing: Receiver<Vec<u8>>,
+    pending: Pending,
+    alive: Arc<AtomicBool>,
+    mux: Weak<Multiplexer<C>>,
+) {
+    let subs = match mux.upgrade() {
+        Some(mux) => mux.subs.clone(),
+        None => return,
+    };
+    let writing = std::thread::spawn(move || write_all(writer, outgoing));
+    let res = read_all(&mut reader, writing, &pending, &subs);
+    {
+        // under the lock so nobody registers on a dead connection
+        let mut p = pending.lock().unwrap();
+        alive.store(false, Ordering::Release);
+        // dropping the senders wakes up everyone still waiting
+        p.clear();
+    }
+    if let Some(mux) = mux.upgrade() {
+        // drops the outgoing sender so a still waiting writer exits
+        let mut live = mux.live.lock().unwrap();
+        if live.as_ref().is_some_and(|l| Arc::ptr_eq(&l.alive, &alive)) {
+            *live = None;
+        }
+    }
+    if let Err(e) = res {
+        warn!("Connection lost: {:?}", e);
+        reconnect(mux);
+    }
+}
+
+/// nobody else would notice the drop if only subscriptions use the connection
+fn reconnect<C: Connector>(mux: Weak<Multiplexer<C>>) {
+    let mut backoff = Duration::from_millis(100);
+    loop {
+        std::thread::sleep(backoff);
+        // don't keep the multiplexer alive while sleeping
+        let mux = match mux.upgrade() {
+            Some(mux) => mux,
+            None => return,
+        };
+        if mux.subs.lock().unwrap().active.is_empty() {
+            return;
+        }
+        match mux.live() {
+            Ok(_) => return,
+            Err(e) => warn!("Reconnect failed: {:?}", e),
+        }
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+}
+
+/// sends queued messages until every sender is dropped
+fn write_all<W: SocketWriter>(mut writer: W, outgoing: Receiver<Vec<u8>>) -> std::io::Result<()> {
+    for msg in outgoing {
+        writer.write_msg(&msg)?;
+    }
+    Ok(())
+}
+
+/// routes incoming messages until the socket or the writer fails, or the writer is done
+fn read_all<R: SocketReader>(
+    reader: &mut R,
+    writing: JoinHandle<std::io::Result<()>>,
+    pending: &Pending,
+    subs: &SharedSubs,
+) -> std::io::Result<()> {
+    while !writing.is_finished() {
+        if let Some(msg) = reader.read_msg()? {
+            match serde_json::from_slice(&msg) {
+                Ok(v) => route(pending, subs, v),
+                Err(e) => warn!("Dropping non json message: {:?}", e),
+            }
+        }
+    }
+    writing
+        .join()
+        .unwrap_or_else(|_| Err(std::io::Error::other("writer panicked")))
+}
+
+fn route(pending: &Pending, subs: &SharedSubs, msg: Value) {
+    match msg {
+        Value::Array(responses) => responses
+            .into_iter()
+            .for_each(|r| route_one(pending, subs, r)),
+        response => route_one(pending, subs, response),
+    }
+}
+
+fn route_one(pending: &Pending, subs: &SharedSubs, mut response: Value) {
+    if response.get("method").and_then(Value::as_str) == Some("eth_subscription") {
+        notify(subs, response);
+        return;
+    }
+    let id = match response.get("id").and_then(Value::as_u64) {
+        Some(id) => id,
+        None => {
+            debug!("Dropping message without id: {}", response);
+            return;
+        }
+    };
+    {
+        let mut subs = subs.lock().unwrap();
+        if let Some(handle) = subs.subscribing.remove(&id) {
+            match response.get("result").and_then(Value::as_str) {
+                Some(server_id) => {
+                    let server_id = server_id.to_string();
+                    if let Some(sub) = subs.active.get_mut(&handle) {
+                        sub.server_id = Some(server_id.clone());
+                        subs.routes.insert(server_id, handle);
+                    }
+                }
+                None => warn!("Subscribe failed: {}", response),
+            }
+        }
+    }
+    match pending.lock().unwrap().remove(&id) {
+        // receiver might have timed out already
+        Some(tx) => {
+            let _ = tx.send(response.take());
+        }
+        None => debug!("Dropping unmatched message: {}", response),
+    }
+}
+
+fn notify(subs: &SharedSubs, mut notification: Value) {
+    let params = &mut notification["params"];
+    let subs = subs.lock().unwrap();
+    let sub = params["subscription"]
+        .as_str()
+        .and_then(|server_id| subs.routes.get(server_id))
+        .and_then(|handle| subs.active.get(handle));
+    match sub {
+        // receiver dropped means unsubscribe is on its way
+        Some(sub) => {
+            let _ = sub.tx.send(params["result"].take());
+        }
+        None => debug!("Dropping notification for unknown subscription: {}", params),
+    }
+}
@@ -0,0 +1,243 @@
+use super::{http::HttpTransport, secret_url::SecretUrl, transport::RpcTransportErr};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// sends every request to all endpoints in parallel,
+/// answers only if at least `min_agree` of them return the same response
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "QuorumConfigRepr")]
+pub struct QuorumConfig {
+    pub endpoints: Vec<HttpTransport>,
+    pub min_agree: usize,
+}
+
+#[derive(Deserialize)]
+struct QuorumConfigRepr {
+    endpoints: Vec<HttpTransport>,
+    min_agree: usize,
+}
+
+impl TryFrom<QuorumConfigRepr> for QuorumConfig {
+    type Error = String;
+    fn try_from(value: QuorumConfigRepr) -> Result<Self, Self::Error> {
+        Self::new(value.endpoints, value.min_agree)
+    }
+}
+
+#[derive(Debug)]
+pub struct NoQuorum {
+    pub required: usize,
+    /// size of the biggest group of matching responses
+    pub best: usize,
+    /// endpoints outside of the biggest group, failed ones included
+    pub disagreeing: Vec<SecretUrl>,
+}
+
+impl std::fmt::Display for NoQuorum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no quorum, {} of the required {} agreed",
+            self.best, self.required
+        )?;
+        if !self.disagreeing.is_empty() {
+            let urls: Vec<String> = self.disagreeing.iter().map(|u| u.to_string()).collect();
+            write!(f, ", disagreeing: {}", urls.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for NoQuorum {}
+
+impl QuorumConfig {
+    /// `min_agree` between 1 and the number of endpoints
+    pub fn new(endpoints: Vec<HttpTransport>, min_agree: usize) -> Result<Self, String> {
+        if min_agree == 0 || min_agree > endpoints.len() {
+            return Err(format!(
+                "quorum min_agree = {} has to be between 1 and the {} endpoints",
+                min_agree,
+                endpoints.len()
+            ));
+        }
+        Ok(Self {
+            endpoints,
+            min_agree,
+        })
+    }
+
+    pub fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send_calls(params, None)
+    }
+
+    /// `methods` of the calls in `params` if known, see `HttpTransport::post_calls`
+    pub(crate) fn send_calls(
+        &self,
+        params: &[u8],
+        methods: Option<&[&str]>,
+    ) -> Result<Vec<u8>, RpcTransportErr> {
+        if self.endpoints.is_empty() {
+            return Err(RpcTransportErr::NoHttps);
+        }
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .endpoints
+                .iter()
+                .map(|http| s.spawn(move || http.post_calls(params, methods)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // nothing to agree on, the network error says more than `NoQuorum`
+        if results.iter().all(|r| r.is_err()) {
+            let e = results.into_iter().find_map(Result::err).expect("endpoints");
+            return Err(RpcTransportErr::Http(e));
+        }
+        let responses: Vec<Option<Value>> = results
+            .into_iter()
+            .zip(self.endpoints.iter())
+            .map(|(res, http)| match res {
+                Ok(res) => serde_json::from_slice(res.as_slice()).ok(),
+                Err(e) => {
+                    warn!("Quorum endpoint {} failed: {:?}", http.url, e);
+                    None
+                }
+            })
+            .collect();
+        let agreed = if serde_json::from_slice::<Value>(params)
+            .map(|v| v.is_array())
+            .unwrap_or(false)
+        {
+            self.agree_batch(responses)?
+        } else {
+            self.agree(
+                responses
+                    .into_iter()
+                    .map(|r| r.filter(|r| !r.is_array()))
+                    .collect(),
+            )?
+        };
+        Ok(serde_json::to_vec(&agreed).expect("value serializes"))
+    }
+
+    /// batch responses are matched up by id, every single one needs a quorum
+    fn agree_batch(&self, responses: Vec<Option<Value>>) -> Result<Value, RpcTransportErr> {
+        let by_id: Vec<HashMap<String, Value>> = responses
+            .into_iter()
+            .map(|r| match r {
+                Some(Value::Array(items)) => items
+                    .into_iter()
+                    .map(|item| (item["id"].to_string(), item))
+                    .collect(),
+                _ => HashMap::new(),
+            })
+            .collect();
+        let mut ids: Vec<&String> = by_id.iter().flat_map(|m| m.keys()).collect();
+        ids.sort();
+        ids.dedup();
+        let mut agreed = Vec::with_capacity(ids.len());
+        for id in ids {
+            agreed.push(self.agree(by_id.iter().map(|m| m.get(id).cloned()).collect())?);
+        }
+        Ok(Value::Array(agreed))
+    }
+
+    /// `responses[i]` came from `endpoints[i]`, compares `result` and `error`
+    fn agree(&self, responses: Vec<Option<Value>>) -> Result<Value, RpcTransportErr> {
+        // (result, error) -> endpoints that returned it
+        let mut groups: Vec<((Value, Value), Vec<usize>)> = Vec::new();
+        for (idx, res) in responses.iter().enumerate() {
+            let res = match res {
+                Some(v) => v,
+                None => continue,
+            };
+            let key = (res["result"].clone(), res["error"].clone());
+            match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, members)) => members.push(idx),
+                None => groups.push((key, vec![idx])),
+            }
+        }
+        let best = groups.iter().max_by_key(|(_, members)| members.len());
+        if let Some((_, members)) = best {
+            if members.len() >= self.min_agree {
+                return Ok(responses[members[0]].clone().unwrap());
+            }
+        }
+        let members = best.map(|(_, m)| m.clone()).unwrap_or_default();
+        Err(RpcTransportErr::NoQuorum(NoQuorum {
+            required: self.min_agree,
+            best: members.len(),
+            disagreeing: (0..self.endpoints.len())
+                .filter(|i| !members.contains(i))
+                .map(|i| self.endpoints[i].url.clone())
+                .collect(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http_mock::serve_result, EthRpc, JRCall, JRError, RetryPolicy, RpcTransport,
+    };
+    use serde_json::json;
+
+    fn client(results: Vec<&str>, min_agree: usize) -> EthRpc {
+        let endpoints = results
+            .into_iter()
+            .map(|r| HttpTransport::new(&serve_result(json!(r))).unwrap())
+            .collect();
+        let quorum = QuorumConfig::new(endpoints, min_agree).unwrap();
+        EthRpc::with_transport(RpcTransport::Quorum(quorum)).with_retry_policy(RetryPolicy::none())
+    }
+
+    #[test]
+    fn test_quorum_reached() {
+        let client = client(vec!["0x1", "0x2", "0x1"], 2);
+        assert_eq!(client.get_block_number().unwrap(), 1);
+        let res = client
+            .batch(vec![
+                JRCall::new_with_id("eth_blockNumber", Vec::new() as Vec<()>, 0).unwrap(),
+                JRCall::new_with_id("eth_blockNumber", Vec::new() as Vec<()>, 1).unwrap(),
+            ])
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].result, json!("0x1"));
+    }
+
+    #[test]
+    fn test_no_quorum() {
+        let client = client(vec!["0x1", "0x2", "0x1"], 3);
+        match client.get_block_number().unwrap_err() {
+            JRError::NoQuorum(e) => {
+                assert_eq!(e.best, 2);
+                assert_eq!(e.disagreeing.len(), 1);
+                if let RpcTransport::Quorum(q) = &client.transport {
+                    assert_eq!(e.disagreeing[0], q.endpoints[1].url);
+                }
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_min_agree() {
+        let url = "http://127.0.0.1:1";
+        let endpoints = vec![HttpTransport::new(url).unwrap(); 2];
+        assert!(QuorumConfig::new(endpoints.clone(), 0).is_err());
+        assert!(QuorumConfig::new(endpoints.clone(), 3).is_err());
+        let config = json!({"endpoints": [url, url], "min_agree": 3});
+        assert!(serde_json::from_value::<QuorumConfig>(config).is_err());
+        let config = json!({"endpoints": [url, url], "min_agree": 2});
+        assert!(serde_json::from_value::<QuorumConfig>(config).is_ok());
+
+        // nobody listens, all endpoints fail before answering
+        let quorum = QuorumConfig::new(endpoints, 2).unwrap();
+        let client = EthRpc::with_transport(RpcTransport::Quorum(quorum))
+            .with_retry_policy(RetryPolicy::none());
+        let err = client.get_block_number().unwrap_err();
+        assert!(err.is_network_or_ratelimit(), "{:?}", err);
+    }
+}
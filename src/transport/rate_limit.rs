@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// client side token bucket of an http endpoint, every request (single call or batch chunk)
+/// blocks the calling thread until the budget allows it instead of running into 429s
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimit {
+    /// budget refilled per second, in `unit`
+    pub per_second: u32,
+    /// bucket size, `per_second` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub unit: RateUnit,
+    /// compute units per method, on top of the built-in `compute_units` table
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub costs: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum RateUnit {
+    /// every call costs 1, a batch as much as it has calls
+    #[default]
+    Requests,
+    /// provider compute units, see `compute_units`
+    ComputeUnits,
+}
+
+/// built-in cost table, roughly alchemy's compute units which other providers copied
+pub fn compute_units(method: &str) -> u32 {
+    match method {
+        "eth_chainId" | "net_version" | "eth_syncing" => 0,
+        "eth_blockNumber" | "eth_feeHistory" | "eth_maxPriorityFeePerGas" | "eth_subscribe" => 10,
+        "eth_getTransactionReceipt" => 15,
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => 16,
+        "eth_getStorageAt" | "eth_getTransactionByHash" => 17,
+        "eth_getBalance" | "eth_gasPrice" => 19,
+        "eth_call" | "eth_getCode" | "eth_getTransactionCount" => 26,
+        "eth_getLogs" => 75,
+        "eth_estimateGas" => 87,
+        "eth_sendRawTransaction" => 250,
+        "debug_traceTransaction" | "debug_traceCall" | "trace_block" => 309,
+        _ => 26,
+    }
+}
+
+/// url, `per_second`, `burst` and `unit`
+type BucketKey = (String, u32, u32, RateUnit);
+
+struct Bucket {
+    /// negative while callers are waiting for their reservation
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// errors on a zero `per_second`
+    pub fn requests_per_second(per_second: u32) -> Result<Self, String> {
+        let limit = Self {
+            per_second,
+            burst: None,
+            unit: RateUnit::Requests,
+            costs: BTreeMap::new(),
+        };
+        limit.validate()?;
+        Ok(limit)
+    }
+
+    /// errors on a zero `per_second`
+    pub fn compute_units_per_second(per_second: u32) -> Result<Self, String> {
+        Ok(Self {
+            unit: RateUnit::ComputeUnits,
+            ..Self::requests_per_second(per_second)?
+        })
+    }
+
+    pub fn cost(&self, method: &str) -> u32 {
+        match self.unit {
+            RateUnit::Requests => 1,
+            RateUnit::ComputeUnits => self
+                .costs
+                .get(method)
+                .copied()
+                .unwrap_or_else(|| compute_units(method)),
+        }
+    }
+
+    /// of the calls of a single request or a batch
+    pub fn calls_cost(&self, methods: &[&str]) -> u32 {
+        methods.iter().map(|m| self.cost(m)).sum()
+    }
+
+    /// of a single call or a batch, garbage costs 1
+    pub fn request_cost(&self, params: &[u8]) -> u32 {
+        let method_cost = |call: &Value| call["method"].as_str().map(|m| self.cost(m)).unwrap_or(1);
+        match serde_json::from_slice::<Value>(params) {
+            Ok(Value::Array(calls)) => calls.iter().map(method_cost).sum(),
+            Ok(call) => method_cost(&call),
+            Err(_) => 1,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.per_second == 0 || self.burst == Some(0) {
+            return Err("rate limit per_second and burst have to be positive".into());
+        }
+        Ok(())
+    }
+
+    /// reserves `cost` in the bucket of `endpoint` and sleeps until it is covered.
+    /// buckets are process wide so clones and separate clients share the provider budget,
+    /// endpoints configured with different limits get their own.
+    /// a zero limit assigned to the pub fields without validation doesn't throttle
+    pub(crate) fn acquire(&self, endpoint: &str, cost: u32) {
+        static BUCKETS: OnceLock<Mutex<HashMap<BucketKey, Bucket>>> = OnceLock::new();
+        if self.validate().is_err() {
+            return;
+        }
+        let burst = self.burst.unwrap_or(self.per_second);
+        let key = (endpoint.to_string(), self.per_second, burst, self.unit);
+        let (cost, rate, burst) = (cost as f64, self.per_second as f64, burst as f64);
+        let wait = {
+            let mut buckets = BUCKETS.get_or_init(Default::default).lock().unwrap();
+            let now = Instant::now();
+            let bucket = buckets.entry(key).or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(burst) - cost;
+            bucket.updated = now;
+            (-bucket.tokens / rate).max(0.0)
+        };
+        if wait > 0.0 {
+            debug!("Rate limit, waiting {:.3}s", wait);
+            std::thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http_mock::serve_result, EthRpc, HttpTransport, JRCall, RpcTransport};
+    use serde_json::json;
+
+    #[test]
+    fn test_cost() {
+        let calls = json!([
+            {"jsonrpc": "2.0", "id": 0, "method": "eth_getLogs", "params": []},
+            {"jsonrpc": "2.0", "id": 1, "method": "eth_blockNumber", "params": []},
+            {"jsonrpc": "2.0", "id": 2, "method": "eth_call", "params": []},
+        ]);
+        let params = serde_json::to_vec(&calls).unwrap();
+        assert_eq!(
+            RateLimit::requests_per_second(10)
+                .unwrap()
+                .request_cost(&params),
+            3
+        );
+        let mut limit = RateLimit::compute_units_per_second(330).unwrap();
+        assert_eq!(limit.request_cost(&params), 75 + 10 + 26);
+        limit.costs.insert("eth_call".into(), 100);
+        assert_eq!(limit.request_cost(&params), 75 + 10 + 100);
+        let single = serde_json::to_vec(&calls[0]).unwrap();
+        assert_eq!(limit.request_cost(&single), 75);
+        assert_eq!(limit.calls_cost(&["eth_getLogs", "eth_call"]), 75 + 100);
+
+        let config: RateLimit = serde_json::from_value(json!({
+            "per_second": 330,
+            "unit": "ComputeUnits",
+            "costs": {"eth_call": 100}
+        }))
+        .unwrap();
+        assert_eq!(config, limit);
+        let invalid: Result<HttpTransport, _> = serde_json::from_value(json!({
+            "url": "https://rpc.ankr.com/eth",
+            "rate_limit": {"per_second": 0}
+        }));
+        assert!(invalid.is_err());
+        assert!(RateLimit::requests_per_second(0).is_err());
+        assert!(RateLimit::compute_units_per_second(0).is_err());
+        let mut limit = RateLimit::requests_per_second(10).unwrap();
+        limit.burst = Some(0);
+        let http = HttpTransport::new("https://rpc.ankr.com/eth").unwrap();
+        assert!(http.with_rate_limit(limit).is_err());
+    }
+
+    #[test]
+    fn test_throttles() {
+        let url = serve_result(json!("0x1"));
+        let http = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(RateLimit::compute_units_per_second(100).unwrap())
+            .unwrap();
+        let client = EthRpc::with_transport(RpcTransport::Http(http));
+        let start = Instant::now();
+        // a full bucket covers the first 10 calls
+        for _ in 0..10 {
+            client.get_block_number().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(300));
+        // 10 more compute units each
+        for _ in 0..5 {
+            client.get_block_number().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        // batch chunks are charged per call
+        let url = serve_result(json!("0x1"));
+        let mut limit = RateLimit::requests_per_second(10).unwrap();
+        limit.burst = Some(4);
+        let http = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(limit)
+            .unwrap();
+        let mut client = EthRpc::with_transport(RpcTransport::Http(http));
+        client.batch_chunk_size = Some(4);
+        let calls = (0..8)
+            .map(|i| JRCall::new_with_id("eth_blockNumber", Vec::new() as Vec<()>, i).unwrap())
+            .collect();
+        let start = Instant::now();
+        assert_eq!(client.batch(calls).unwrap().len(), 8);
+        assert!(start.elapsed() >= Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_bucket_per_limit() {
+        // a client with a bigger budget for the same url doesn't eat into the small one
+        let url = serve_result(json!("0x1"));
+        let small = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(RateLimit::requests_per_second(2).unwrap())
+            .unwrap();
+        let big = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(RateLimit::requests_per_second(1000).unwrap())
+            .unwrap();
+        let big = EthRpc::with_transport(RpcTransport::Http(big));
+        for _ in 0..10 {
+            big.get_block_number().unwrap();
+        }
+        let start = Instant::now();
+        let small = EthRpc::with_transport(RpcTransport::Http(small));
+        small.get_block_number().unwrap();
+        small.get_block_number().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+
+        // nor does the same number in another unit
+        let url = serve_result(json!("0x1"));
+        let requests = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(RateLimit::requests_per_second(20).unwrap())
+            .unwrap();
+        let units = HttpTransport::new(&url)
+            .unwrap()
+            .with_rate_limit(RateLimit::compute_units_per_second(20).unwrap())
+            .unwrap();
+        let requests = EthRpc::with_transport(RpcTransport::Http(requests));
+        for _ in 0..20 {
+            requests.get_block_number().unwrap();
+        }
+        let start = Instant::now();
+        let units = EthRpc::with_transport(RpcTransport::Http(units));
+        units.get_block_number().unwrap();
+        units.get_block_number().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+    }
+}
@@ -1,11 +1,34 @@
 use super::{
+    balanced::{BalancedConfig, EndpointStats},
+    circuit_breaker::CircuitState,
     env_http::EnvHttp,
+    failover::FailoverConfig,
     http::{HttpErr, HttpTransport},
     ipc::{IpcConfig, IpcError},
+    multiplex::Subscribed,
+    quorum::{NoQuorum, QuorumConfig},
+    secret_url::SecretUrl,
+    ws::{WsConfig, WsError},
 };
 use crate::JRError;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+/// carries json rpc request bytes to a node and the response bytes back,
+/// implement it to plug in your own gateway or a test double with `RpcTransport::Custom`
+pub trait Transport: Debug + Send + Sync {
+    /// `params` is a single request or a batch, the response has to match.
+    /// return `RpcTransportErr::Http(HttpErr::IO(..))` for errors worth retrying,
+    /// `ConnectionRefused` ones are resent even for transactions
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr>;
+    /// `send` knowing the methods of the calls in `params`, in order,
+    /// rate limited endpoints charge them without parsing the body
+    fn send_calls(&self, params: &[u8], _methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send(params)
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum RpcTransport {
@@ -13,17 +36,44 @@ pub enum RpcTransport {
     Http(HttpTransport),
     RandomizeHttps(Vec<HttpTransport>),
     EnvHttp(EnvHttp),
+    Ws(WsConfig),
+    Failover(FailoverConfig),
+    Quorum(QuorumConfig),
+    Balanced(BalancedConfig),
+    /// not (de)serializable, shared between clones
+    #[serde(skip)]
+    Custom(Arc<dyn Transport>),
 }
 
 #[derive(Debug)]
 pub enum RpcTransportErr {
     Ipc(IpcError),
     Http(HttpErr),
+    Ws(WsError),
     FailedToGetEnv(String),
     NoHttps,
+    /// endpoints of a `Quorum` transport did not agree
+    NoQuorum(NoQuorum),
+    /// only persistent transports (`Ws`, `Ipc`) can push notifications
+    SubscriptionsUnsupported,
+    /// from a `Custom` transport
+    Custom(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl RpcTransportErr {
+    /// stable name for grouping in logs and alerts, that of the inner error where there is one
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Ipc(e) => e.kind(),
+            Self::Http(e) => e.kind(),
+            Self::Ws(e) => e.kind(),
+            Self::FailedToGetEnv(_) => "env",
+            Self::NoHttps => "no_https",
+            Self::NoQuorum(_) => "no_quorum",
+            Self::SubscriptionsUnsupported => "subscriptions_unsupported",
+            Self::Custom(_) => "custom_transport",
+        }
+    }
     pub fn is_http_network(&self) -> bool {
         if let Self::Http(e) = &self {
             if let HttpErr::IO(_) = e {
@@ -32,6 +82,86 @@ impl RpcTransportErr {
         }
         false
     }
+    /// everything but a malformed request is worth retrying, the connection is reestablished
+    pub fn is_ws_network(&self) -> bool {
+        matches!(self, Self::Ws(e) if !matches!(e, WsError::InvalidRequest(_)))
+    }
+    /// same as ws, the persistent connection reconnects to a restarted node
+    pub fn is_ipc_network(&self) -> bool {
+        matches!(self, Self::Ipc(e) if !matches!(e, IpcError::InvalidRequest(_)))
+    }
+    /// 429 too many requests or 503 service unavailable
+    pub fn is_http_busy(&self) -> bool {
+        matches!(self, Self::Http(e) if e.is_busy())
+    }
+    /// as sent by the server with a 429 or 503
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Http(HttpErr::Busy { retry_after, .. }) => *retry_after,
+            _ => None,
+        }
+    }
+    /// the request surely never reached the node, safe to resend even if not idempotent.
+    /// not a 503, load balancers send it after forwarding the request too
+    pub fn is_rejected(&self) -> bool {
+        match self {
+            Self::Http(HttpErr::IO(e)) => e.kind() == std::io::ErrorKind::ConnectionRefused,
+            Self::Http(HttpErr::Busy { status, .. }) => **status == 429,
+            _ => false,
+        }
+    }
+}
+
+/// transparent for the wrapped errors
+impl std::fmt::Display for RpcTransportErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ipc(e) => std::fmt::Display::fmt(e, f),
+            Self::Http(e) => std::fmt::Display::fmt(e, f),
+            Self::Ws(e) => std::fmt::Display::fmt(e, f),
+            Self::FailedToGetEnv(msg) => write!(f, "failed to get the rpc url from env: {}", msg),
+            Self::NoHttps => write!(f, "no http endpoints configured"),
+            Self::NoQuorum(e) => std::fmt::Display::fmt(e, f),
+            Self::SubscriptionsUnsupported => write!(f, "subscriptions need a ws or ipc transport"),
+            Self::Custom(e) => std::fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl std::error::Error for RpcTransportErr {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ipc(e) => std::error::Error::source(e),
+            Self::Http(e) => std::error::Error::source(e),
+            Self::Ws(e) => std::error::Error::source(e),
+            Self::Custom(e) => std::error::Error::source(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<IpcError> for RpcTransportErr {
+    fn from(e: IpcError) -> Self {
+        Self::Ipc(e)
+    }
+}
+
+impl From<HttpErr> for RpcTransportErr {
+    fn from(e: HttpErr) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<WsError> for RpcTransportErr {
+    fn from(e: WsError) -> Self {
+        Self::Ws(e)
+    }
+}
+
+impl From<NoQuorum> for RpcTransportErr {
+    fn from(e: NoQuorum) -> Self {
+        Self::NoQuorum(e)
+    }
 }
 
 impl RpcTransport {
@@ -43,28 +173,231 @@ impl RpcTransport {
         ))
     }
 
+    pub fn with_ws(ws: impl ToString) -> Result<Self, JRError> {
+        Ok(RpcTransport::Ws(
+            WsConfig::new(ws.to_string().as_str()).map_err(JRError::FailInitialize)?,
+        ))
+    }
+
+    /// `Custom` from anything implementing `Transport`
+    pub fn custom(transport: impl Transport + 'static) -> Self {
+        Self::Custom(Arc::new(transport))
+    }
+
     pub fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
-        let http = match self {
-            Self::EnvHttp(v) => {
-                // have to return here because env returns obj not reference
-                return v
-                    .get_http()?
-                    .post(params)
-                    .map_err(|e| RpcTransportErr::Http(e));
-            }
-            // if ipc return
-            Self::Ipc(ipc) => return ipc.send_ipc(params).map_err(|e| RpcTransportErr::Ipc(e)),
-            // else keep http
-            Self::RandomizeHttps(https) => {
-                if https.len() == 0 {
-                    return Err(RpcTransportErr::NoHttps);
-                }
-                let mut rng = rand::thread_rng();
-                let idx = rng.gen_range(0..https.len());
-                &https[idx]
-            }
-            Self::Http(http) => &http,
+        self.inner().send(params)
+    }
+
+    /// `send` with the methods of the calls in `params`, see `Transport::send_calls`
+    pub fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.inner().send_calls(params, methods)
+    }
+
+    fn inner(&self) -> &dyn Transport {
+        match self {
+            Self::Ipc(ipc) => ipc,
+            Self::Http(http) => http,
+            Self::RandomizeHttps(https) => https,
+            Self::EnvHttp(env) => env,
+            Self::Ws(ws) => ws,
+            Self::Failover(failover) => failover,
+            Self::Quorum(quorum) => quorum,
+            Self::Balanced(balanced) => balanced,
+            Self::Custom(custom) => custom.as_ref(),
+        }
+    }
+
+    /// every http endpoint with a `circuit_breaker`, for health checks
+    pub fn circuit_states(&self) -> Vec<(SecretUrl, CircuitState)> {
+        let endpoints: Vec<&HttpTransport> = match self {
+            Self::Http(http) => vec![http],
+            Self::RandomizeHttps(endpoints) => endpoints.iter().collect(),
+            Self::Failover(failover) => failover.endpoints.iter().collect(),
+            Self::Quorum(quorum) => quorum.endpoints.iter().collect(),
+            Self::Balanced(balanced) => balanced.endpoints.iter().collect(),
+            _ => Vec::new(),
         };
-        http.post(params).map_err(|e| RpcTransportErr::Http(e))
+        endpoints
+            .into_iter()
+            .filter_map(|http| Some((http.url.clone(), http.circuit_state()?)))
+            .collect()
+    }
+
+    /// latency and error stats per endpoint, empty unless `Balanced`
+    pub fn endpoint_stats(&self) -> Vec<EndpointStats> {
+        match self {
+            Self::Balanced(balanced) => balanced.stats(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// `eth_subscribe` with `params`
+    pub(crate) fn subscribe(&self, params: Value) -> Result<Subscribed, RpcTransportErr> {
+        match self {
+            Self::Ws(ws) => ws.subscribe(params).map_err(RpcTransportErr::Ws),
+            Self::Ipc(ipc) => ipc.subscribe(params).map_err(RpcTransportErr::Ipc),
+            _ => Err(RpcTransportErr::SubscriptionsUnsupported),
+        }
+    }
+
+    pub(crate) fn unsubscribe(&self, handle: u64) {
+        match self {
+            Self::Ws(ws) => ws.unsubscribe(handle),
+            Self::Ipc(ipc) => ipc.unsubscribe(handle),
+            _ => {}
+        }
+    }
+}
+
+impl Transport for RpcTransport {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.inner().send(params)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        RpcTransport::send_calls(self, params, methods)
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.post(params).map_err(RpcTransportErr::Http)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.post_calls(params, Some(methods))
+            .map_err(RpcTransportErr::Http)
+    }
+}
+
+/// `RandomizeHttps`, uniformly random endpoint per request
+impl Transport for Vec<HttpTransport> {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        if self.is_empty() {
+            return Err(RpcTransportErr::NoHttps);
+        }
+        let idx = rand::thread_rng().gen_range(0..self.len());
+        self[idx].post(params).map_err(RpcTransportErr::Http)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        if self.is_empty() {
+            return Err(RpcTransportErr::NoHttps);
+        }
+        let idx = rand::thread_rng().gen_range(0..self.len());
+        self[idx]
+            .post_calls(params, Some(methods))
+            .map_err(RpcTransportErr::Http)
+    }
+}
+
+impl Transport for EnvHttp {
+    /// env is read on every request
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.get_http()?.post(params).map_err(RpcTransportErr::Http)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.get_http()?
+            .post_calls(params, Some(methods))
+            .map_err(RpcTransportErr::Http)
+    }
+}
+
+impl Transport for IpcConfig {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send_ipc(params).map_err(RpcTransportErr::Ipc)
+    }
+}
+
+impl Transport for WsConfig {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        self.send_ws(params).map_err(RpcTransportErr::Ws)
+    }
+}
+
+impl Transport for FailoverConfig {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        FailoverConfig::send(self, params)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        FailoverConfig::send_calls(self, params, Some(methods))
+    }
+}
+
+impl Transport for QuorumConfig {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        QuorumConfig::send(self, params)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        QuorumConfig::send_calls(self, params, Some(methods))
+    }
+}
+
+impl Transport for BalancedConfig {
+    fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+        BalancedConfig::send(self, params)
+    }
+    fn send_calls(&self, params: &[u8], methods: &[&str]) -> Result<Vec<u8>, RpcTransportErr> {
+        BalancedConfig::send_calls(self, params, Some(methods))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EthRpc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// answers every request with block 0x10
+    #[derive(Debug, Default)]
+    struct Double {
+        calls: AtomicUsize,
+    }
+
+    impl Transport for Double {
+        fn send(&self, params: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let call: Value = serde_json::from_slice(params).unwrap();
+            Ok(serde_json::to_vec(&serde_json::json!({
+                "jsonrpc": "2.0", "id": call["id"], "result": "0x10"
+            }))
+            .unwrap())
+        }
+    }
+
+    #[test]
+    fn test_custom_transport() {
+        let double = Arc::new(Double::default());
+        let client = EthRpc::with_transport(RpcTransport::Custom(double.clone()));
+        assert_eq!(client.clone().get_block_number().unwrap(), 16);
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        assert_eq!(double.calls.load(Ordering::SeqCst), 2);
+        assert!(serde_json::to_string(&client).is_err());
+        assert!(matches!(
+            client.subscribe_new_heads().unwrap_err(),
+            JRError::Transport(RpcTransportErr::SubscriptionsUnsupported)
+        ));
+    }
+
+    #[derive(Debug)]
+    struct Refused;
+
+    impl Transport for Refused {
+        fn send(&self, _: &[u8]) -> Result<Vec<u8>, RpcTransportErr> {
+            let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+            Err(HttpErr::IO(io).into())
+        }
+    }
+
+    #[test]
+    fn test_error_chain() {
+        fn block_number(client: &EthRpc) -> Result<u64, Box<dyn std::error::Error>> {
+            Ok(client.get_block_number()?)
+        }
+        let mut client = EthRpc::with_transport(RpcTransport::Custom(Arc::new(Refused)));
+        client.disable_ratelimit_protection = true;
+        let err = client.get_block_number().unwrap_err();
+        assert_eq!(err.kind(), "http_io");
+        assert_eq!(err.to_string(), "http request failed");
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "refused");
+        assert!(block_number(&client).is_err());
     }
 }
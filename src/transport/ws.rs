@@ -0,0 +1,408 @@
+use super::{
+    multiplex::{
+        Connector, Multiplexer, MuxErr, SocketReader, SocketWriter, Subscribed, SOCKET_READ_TIMEOUT,
+    },
+    secret_url::SecretUrl,
+};
+use oxhttp::model::Url;
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io::ErrorKind,
+    net::TcpStream,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tungstenite::{
+    client::IntoClientRequest, error::UrlError, handshake::HandshakeError, stream::MaybeTlsStream,
+    Message, WebSocket,
+};
+
+/// persistent websocket connection, shared between clones
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WsConfig {
+    #[serde(deserialize_with = "validate_ws_url")]
+    pub ws_url: SecretUrl,
+    /// how long to wait for a response
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout_millis: u64,
+    #[serde(skip)]
+    conn: Arc<OnceLock<Arc<Multiplexer<WsConnector>>>>,
+}
+
+fn default_read_timeout() -> u64 {
+    10_000
+}
+
+fn check_ws_url(s: &str) -> Result<(), String> {
+    let url = Url::parse(s).map_err(|e| format!("{:?}", e))?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err(format!("Not a websocket url scheme: {}", url.scheme()));
+    }
+    Ok(())
+}
+
+fn validate_ws_url<'de, D>(deserializer: D) -> Result<SecretUrl, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = Cow::<str>::deserialize(deserializer)?;
+    check_ws_url(&s).map_err(D::Error::custom)?;
+    Ok(SecretUrl::new(s))
+}
+
+#[derive(Debug)]
+pub enum WsError {
+    Connect(Box<tungstenite::Error>),
+    InvalidRequest(serde_json::Error),
+    /// no response within `read_timeout_millis`
+    Timeout,
+    /// connection dropped while waiting, next request reconnects
+    Disconnected,
+}
+
+impl WsError {
+    /// stable name for grouping in logs and alerts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connect(_) => "ws_connect",
+            Self::InvalidRequest(_) => "ws_invalid_request",
+            Self::Timeout => "ws_timeout",
+            Self::Disconnected => "ws_disconnected",
+        }
+    }
+}
+
+impl std::fmt::Display for WsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect(_) => write!(f, "ws connection failed"),
+            Self::InvalidRequest(_) => write!(f, "invalid ws request"),
+            Self::Timeout => write!(f, "ws request timed out"),
+            Self::Disconnected => write!(f, "ws connection dropped"),
+        }
+    }
+}
+
+impl std::error::Error for WsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Connect(e) => Some(e.as_ref()),
+            Self::InvalidRequest(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MuxErr<Box<tungstenite::Error>>> for WsError {
+    fn from(e: MuxErr<Box<tungstenite::Error>>) -> Self {
+        match e {
+            MuxErr::Connect(e) => Self::Connect(e),
+            MuxErr::InvalidRequest(e) => Self::InvalidRequest(e),
+            MuxErr::Timeout => Self::Timeout,
+            MuxErr::Disconnected => Self::Disconnected,
+        }
+    }
+}
+
+impl WsConfig {
+    pub fn new(ws_url: &str) -> Result<Self, String> {
+        check_ws_url(ws_url).map_err(|e| {
+            format!(
+                "Failed to parse ws transport url = {} err = {:?}",
+                SecretUrl::new(ws_url),
+                e
+            )
+        })?;
+        Ok(Self {
+            ws_url: SecretUrl::new(ws_url),
+            read_timeout_millis: default_read_timeout(),
+            conn: Default::default(),
+        })
+    }
+
+    /// connects lazily on first use and reconnects if the connection dropped
+    pub fn send_ws(&self, params: &[u8]) -> Result<Vec<u8>, WsError> {
+        Ok(self.multiplexer().send(params)?)
+    }
+
+    pub(crate) fn subscribe(&self, params: Value) -> Result<Subscribed, WsError> {
+        Ok(self.multiplexer().subscribe(params)?)
+    }
+
+    pub(crate) fn unsubscribe(&self, handle: u64) {
+        self.multiplexer().unsubscribe(handle)
+    }
+
+    fn multiplexer(&self) -> &Arc<Multiplexer<WsConnector>> {
+        self.conn.get_or_init(|| {
+            Arc::new(Multiplexer::new(
+                WsConnector {
+                    url: self.ws_url.clone(),
+                },
+                Duration::from_millis(self.read_timeout_millis),
+            ))
+        })
+    }
+}
+
+struct WsConnector {
+    url: SecretUrl,
+}
+
+/// built here instead of by tungstenite, so a crate enabling its native-tls feature
+/// doesn't switch the backend under us
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+            Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+type Ws = Arc<Mutex<WebSocket<MaybeTlsStream<TcpStream>>>>;
+
+fn boxed(e: impl Into<tungstenite::Error>) -> Box<tungstenite::Error> {
+    Box::new(e.into())
+}
+
+impl Connector for WsConnector {
+    type Reader = WsReader;
+    type Writer = WsWriter;
+    type Error = Box<tungstenite::Error>;
+    fn connect(&self) -> Result<(WsReader, WsWriter), Self::Error> {
+        let request = self.url.expose().into_client_request().map_err(boxed)?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| boxed(UrlError::NoHostName))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("wss") => 443,
+            _ => 80,
+        });
+        let stream = TcpStream::connect((host, port)).map_err(boxed)?;
+        stream.set_nodelay(true).map_err(boxed)?;
+        // same socket, waits for data without holding the lock the writer needs
+        let tcp = stream.try_clone().map_err(boxed)?;
+        let connector = tungstenite::Connector::Rustls(tls_config());
+        let (ws, _) = tungstenite::client_tls_with_config(request, stream, None, Some(connector))
+            .map_err(|e| match e {
+            HandshakeError::Failure(e) => boxed(e),
+            HandshakeError::Interrupted(_) => boxed(std::io::Error::from(ErrorKind::WouldBlock)),
+        })?;
+        tcp.set_read_timeout(Some(SOCKET_READ_TIMEOUT))
+            .map_err(boxed)?;
+        let ws = Arc::new(Mutex::new(ws));
+        let reader = WsReader {
+            ws: ws.clone(),
+            tcp,
+            queued: VecDeque::new(),
+            failed: None,
+        };
+        Ok((reader, WsWriter(ws)))
+    }
+}
+
+struct WsWriter(Ws);
+
+struct WsReader {
+    ws: Ws,
+    tcp: TcpStream,
+    /// read along with an earlier message
+    queued: VecDeque<Vec<u8>>,
+    /// hit while draining, reported after the messages read before it
+    failed: Option<std::io::Error>,
+}
+
+fn to_io(e: tungstenite::Error) -> std::io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => std::io::Error::other(e),
+    }
+}
+
+impl SocketWriter for WsWriter {
+    fn write_msg(&mut self, msg: &[u8]) -> std::io::Result<()> {
+        let text = String::from_utf8(msg.to_vec())
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        self.0
+            .lock()
+            .unwrap()
+            .send(Message::Text(text))
+            .map_err(to_io)
+    }
+}
+
+impl WsReader {
+    fn next_queued(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        match self.queued.pop_front() {
+            Some(msg) => Some(Ok(msg)),
+            None => self.failed.take().map(Err),
+        }
+    }
+
+    /// everything the tls and websocket buffers hold, the socket itself may stay quiet after
+    fn drain(&mut self, ws: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> std::io::Result<()> {
+        loop {
+            match ws.read() {
+                Ok(Message::Text(s)) => self.queued.push_back(s.into_bytes()),
+                Ok(Message::Binary(b)) => self.queued.push_back(b),
+                Ok(Message::Close(_)) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "closed by peer",
+                    ))
+                }
+                // pings are answered by tungstenite
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(())
+                }
+                Err(e) => return Err(to_io(e)),
+            }
+        }
+    }
+}
+
+impl SocketReader for WsReader {
+    fn read_msg(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(res) = self.next_queued() {
+            return res.map(Some);
+        }
+        match self.tcp.peek(&mut [0]) {
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+        // the writer only touches the socket under the lock, so it never sees it non blocking
+        let ws = self.ws.clone();
+        let mut ws = ws.lock().unwrap();
+        self.tcp.set_nonblocking(true)?;
+        if let Err(e) = self.drain(&mut ws) {
+            self.failed = Some(e);
+        }
+        self.tcp.set_nonblocking(false)?;
+        self.next_queued().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{EthRpc, JRCall, RpcTransport};
+    use serde_json::json;
+    use std::net::TcpListener;
+
+    /// answers every call with its own method name, closes after `close_after` messages
+    fn mock_server(close_after: Option<usize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut ws = tungstenite::accept(stream.unwrap()).unwrap();
+                let mut count = 0;
+                while let Ok(msg) = ws.read() {
+                    if !msg.is_text() {
+                        continue;
+                    }
+                    let req: Value = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+                    let answer = |c: &Value| {
+                        let result = match c["method"].as_str().unwrap() {
+                            "eth_blockNumber" => json!("0x10"),
+                            m => json!(m),
+                        };
+                        json!({"jsonrpc": "2.0", "id": c["id"], "result": result})
+                    };
+                    let res = match &req {
+                        Value::Array(calls) => Value::Array(calls.iter().map(answer).collect()),
+                        call => answer(call),
+                    };
+                    ws.send(Message::Text(res.to_string())).unwrap();
+                    count += 1;
+                    if Some(count) == close_after {
+                        ws.close(None).unwrap();
+                        let _ = ws.flush();
+                        break;
+                    }
+                }
+            }
+        });
+        format!("ws://{}", addr)
+    }
+
+    #[test]
+    fn test_deserialize() {
+        assert!(WsConfig::new("https://etherscan.io/").is_err());
+        assert!(serde_json::from_value::<RpcTransport>(json!({
+            "Ws": { "ws_url": "wss://eth-mainnet.g.alchemy.com/v2/fake" }
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_ws_rpc_and_batch() {
+        let client = EthRpc::with_ws(mock_server(None)).unwrap();
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        let results = client
+            .batch(vec![
+                JRCall::new_with_id("eth_chainId", Vec::new() as Vec<()>, 0).unwrap(),
+                JRCall::new_with_id("eth_gasPrice", Vec::new() as Vec<()>, 1).unwrap(),
+            ])
+            .unwrap();
+        assert_eq!(results[0].id, 0);
+        assert_eq!(results[0].result, json!("eth_chainId"));
+        assert_eq!(results[1].id, 1);
+        assert_eq!(results[1].result, json!("eth_gasPrice"));
+    }
+
+    #[test]
+    fn test_ws_concurrent() {
+        let client = EthRpc::with_ws(mock_server(None)).unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let client = client.clone();
+                std::thread::spawn(move || {
+                    let method = format!("method_{}", i);
+                    let res: String = client
+                        .no_ratelimit_rpc(
+                            JRCall::new(method.clone(), Vec::new() as Vec<()>).unwrap(),
+                        )
+                        .unwrap();
+                    assert_eq!(res, method);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ws_reconnect() {
+        let client = EthRpc::with_ws(mock_server(Some(1))).unwrap();
+        assert_eq!(client.get_block_number().unwrap(), 16);
+        // let the reader thread notice the close
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(client.get_block_number().unwrap(), 16);
+    }
+}